@@ -1,11 +1,30 @@
 //! Basic usage example for typed-sled.
 
+use base64 as _;
+#[cfg(feature = "bincode")]
+use bincode as _;
+#[cfg(feature = "encryption")]
+use chacha20poly1305 as _;
+#[cfg(feature = "checksum")]
+use crc32c as _;
 use dashmap as _;
+#[cfg(feature = "async")]
+use futures_core as _;
+#[cfg(feature = "lz4")]
+use lz4_flex as _;
+#[cfg(feature = "postcard")]
+use postcard as _;
 use rkyv::rancor::Error as RkyvError;
 use rkyv::util::AlignedVec;
 use rkyv::{Archive, Deserialize, Serialize, from_bytes, to_bytes};
+#[cfg(any(feature = "serde-json", feature = "bincode", feature = "postcard"))]
+use serde as _;
+#[cfg(feature = "serde-json")]
+use serde_json as _;
 use thiserror as _;
 use typed_sled::{CodecError, Schema, SledDb, SledTree, TreeName, ValueCodec, error::Result};
+#[cfg(feature = "derive")]
+use typed_sled_derive as _;
 
 #[derive(Archive, Serialize, Deserialize, Debug, Clone)]
 struct User {
@@ -1,21 +1,35 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, sync::Arc};
 
-use sled::Batch;
+use sled::{Batch, IVec};
 
-use crate::{KeyCodec, Schema, ValueCodec, error::Result};
+use crate::{
+    KeyCodec, Schema, ValueCodec, codec::CodecContext, encryption::KeyProvider, error::Result,
+};
 
 /// Type-safe wrapper around a sled batch for atomic operations.
 #[derive(Debug)]
 pub struct SledBatch<S: Schema> {
-    pub(crate) inner: Batch,
+    /// Raw operations in the order they were added, where `None` removes
+    /// the key.
+    pub(crate) ops: Vec<(IVec, Option<IVec>)>,
+    key_provider: Option<Arc<dyn KeyProvider>>,
     _phantom: PhantomData<S>,
 }
 
 impl<S: Schema> SledBatch<S> {
     /// Creates a new empty batch.
+    ///
+    /// The batch has no key provider; use [`SledTree::batch`](crate::SledTree::batch)
+    /// for schemas with encrypted values.
     pub fn new() -> Self {
+        Self::with_key_provider(None)
+    }
+
+    /// Creates an empty batch whose codecs can use `key_provider`.
+    pub(crate) fn with_key_provider(key_provider: Option<Arc<dyn KeyProvider>>) -> Self {
         Self {
-            inner: Batch::default(),
+            ops: Vec::new(),
+            key_provider,
             _phantom: PhantomData,
         }
     }
@@ -23,17 +37,34 @@ impl<S: Schema> SledBatch<S> {
     /// Adds an insert operation to the batch.
     pub fn insert(&mut self, key: S::Key, value: S::Value) -> Result<()> {
         let key = key.encode_key()?;
-        let value = value.encode_value()?;
-        self.inner.insert(key, value);
+        let ctx = CodecContext::new(self.key_provider.as_deref()).with_key(&key);
+        let value = value.encode_value_with(&ctx)?;
+        self.ops.push((key.into(), Some(value.into())));
         Ok(())
     }
 
     /// Adds a remove operation to the batch.
     pub fn remove(&mut self, key: S::Key) -> Result<()> {
-        let key = key.encode_key()?;
-        self.inner.remove(key);
+        self.remove_raw(key.encode_key()?);
         Ok(())
     }
+
+    /// Adds a remove operation for the raw key `key` to the batch.
+    pub(crate) fn remove_raw(&mut self, key: impl Into<IVec>) {
+        self.ops.push((key.into(), None));
+    }
+
+    /// Returns the operations of the batch as a sled batch.
+    pub(crate) fn to_sled(&self) -> Batch {
+        let mut batch = Batch::default();
+        for (key, value) in &self.ops {
+            match value {
+                Some(value) => batch.insert(key.clone(), value.clone()),
+                None => batch.remove(key.clone()),
+            }
+        }
+        batch
+    }
 }
 
 impl<S: Schema> Default for SledBatch<S> {
@@ -0,0 +1,206 @@
+use std::ops::Deref;
+
+use sled::IVec;
+
+use crate::{CodecError, CodecResult, Schema, ValueCodec, codec::CodecContext};
+
+/// Length of the CRC32C checksum appended to every value.
+const CHECKSUM_LEN: usize = 4;
+
+/// Value codec combinator that appends a CRC32C checksum to the output of
+/// codec `C`.
+///
+/// The checksum is written as 4 big-endian bytes after the encoded value
+/// and is checked before `C` decodes it, so on-disk corruption surfaces as
+/// [`CodecError::ChecksumMismatch`] instead of an opaque decoding error.
+/// [`SledDb::verify_checksums`](crate::SledDb::verify_checksums) checks a
+/// whole tree without decoding any value.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checksummed<C>(pub C);
+
+impl<C> Checksummed<C> {
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+impl<C> From<C> for Checksummed<C> {
+    fn from(value: C) -> Self {
+        Self(value)
+    }
+}
+
+impl<C> Deref for Checksummed<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// A value codec whose stored bytes can be checked for corruption without
+/// decoding them.
+pub trait ChecksummedValue {
+    /// Returns `true` if `buf` carries a valid checksum.
+    fn verify_checksum(buf: &[u8]) -> bool;
+}
+
+impl<C> ChecksummedValue for Checksummed<C> {
+    fn verify_checksum(buf: &[u8]) -> bool {
+        split_checksum(buf).is_some()
+    }
+}
+
+/// Splits `buf` into its body if the trailing checksum matches.
+fn split_checksum(buf: &[u8]) -> Option<&[u8]> {
+    let split = buf.len().checked_sub(CHECKSUM_LEN)?;
+    let (body, checksum) = buf.split_at(split);
+    (crc32c::crc32c(body).to_be_bytes() == checksum).then_some(body)
+}
+
+impl<S, C> ValueCodec<S> for Checksummed<C>
+where
+    S: Schema,
+    C: ValueCodec<S>,
+{
+    type Decoded = C::Decoded;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        self.encode_value_with(&CodecContext::default())
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        Self::decode_value_with(buf, &CodecContext::default())
+    }
+
+    fn encode_value_with(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        let mut buf = self.0.encode_value_with(ctx)?;
+        let checksum = crc32c::crc32c(&buf);
+        buf.extend_from_slice(&checksum.to_be_bytes());
+        Ok(buf)
+    }
+
+    fn decode_value_with(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self::Decoded> {
+        C::decode_value_with(checked_body::<S>(buf, ctx)?, ctx)
+    }
+
+    const STABLE_ENCODING: bool = C::STABLE_ENCODING;
+
+    fn stable_form(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<IVec> {
+        C::stable_form(checked_body::<S>(buf, ctx)?, ctx)
+    }
+
+    fn encode_stable_form(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        self.0.encode_stable_form(ctx)
+    }
+}
+
+/// Returns the body of a value written by [`Checksummed`], failing if its
+/// checksum does not match.
+fn checked_body<S: Schema>(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<IVec> {
+    let Some(body) = split_checksum(&buf) else {
+        return Err(CodecError::ChecksumMismatch {
+            schema: S::TREE_NAME.0,
+            key: ctx.key().to_vec(),
+        });
+    };
+    Ok(buf.subslice(0, body.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{KeyCodec, Rkyv, SledTree, TreeName, error::Error, test_utils::*};
+
+    #[derive(Debug)]
+    struct ChecksummedSchema;
+
+    impl Schema for ChecksummedSchema {
+        const TREE_NAME: TreeName = TreeName("checksummed");
+        type Key = u32;
+        type Value = Checksummed<Rkyv<TestValue>>;
+    }
+
+    fn insert_values(tree: &SledTree<ChecksummedSchema>) {
+        for value in [TestValue::alice(), TestValue::bob(), TestValue::charlie()] {
+            tree.insert(&value.id, &Checksummed(Rkyv::new(value.clone())))
+                .unwrap();
+        }
+    }
+
+    fn corrupt(tree: &SledTree<ChecksummedSchema>, key: u32) -> Vec<u8> {
+        let key = <u32 as KeyCodec<ChecksummedSchema>>::encode_key(&key).unwrap();
+        let mut raw = tree.inner.get(&key).unwrap().unwrap().to_vec();
+        raw[0] ^= 0x80;
+        tree.inner.insert(&key, raw).unwrap();
+        key
+    }
+
+    #[test]
+    fn test_checksummed_roundtrip() {
+        let tree = create_temp_tree::<ChecksummedSchema>().unwrap();
+        insert_values(&tree);
+
+        let key = <u32 as KeyCodec<ChecksummedSchema>>::encode_key(&1).unwrap();
+        let raw = tree.inner.get(key).unwrap().unwrap();
+        let (body, checksum) = raw.split_at(raw.len() - CHECKSUM_LEN);
+        assert_eq!(checksum, crc32c::crc32c(body).to_be_bytes());
+
+        assert_test_values_eq(&TestValue::alice(), &tree.get(&1).unwrap().unwrap());
+        assert_eq!(tree.iter().count(), 3);
+    }
+
+    #[test]
+    fn test_corrupted_value_reports_key() {
+        let tree = create_temp_tree::<ChecksummedSchema>().unwrap();
+        insert_values(&tree);
+        let bad_key = corrupt(&tree, 2);
+
+        match tree.get(&2) {
+            Err(Error::CodecError(CodecError::ChecksumMismatch { schema, key })) => {
+                assert_eq!(schema, ChecksummedSchema::TREE_NAME.0);
+                assert_eq!(key, bad_key);
+            }
+            other => panic!("expected checksum mismatch, got {other:?}"),
+        }
+
+        let results: Vec<_> = tree.iter().collect();
+        assert!(results[0].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(Error::CodecError(CodecError::ChecksumMismatch { .. }))
+        ));
+        assert!(results[2].is_ok());
+    }
+
+    #[test]
+    fn test_truncated_value_is_rejected() {
+        let tree = create_temp_tree::<ChecksummedSchema>().unwrap();
+        let key = <u32 as KeyCodec<ChecksummedSchema>>::encode_key(&1).unwrap();
+        tree.inner.insert(key, vec![1_u8, 2]).unwrap();
+
+        assert!(matches!(
+            tree.get(&1),
+            Err(Error::CodecError(CodecError::ChecksumMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let db = create_test_db().unwrap();
+        let tree = db.get_tree::<ChecksummedSchema>().unwrap();
+        insert_values(&tree);
+        assert!(
+            db.verify_checksums::<ChecksummedSchema>()
+                .unwrap()
+                .is_empty()
+        );
+
+        let bad_1 = corrupt(&tree, 1);
+        let bad_3 = corrupt(&tree, 3);
+
+        let corrupted = db.verify_checksums::<ChecksummedSchema>().unwrap();
+        assert_eq!(corrupted, vec![bad_1, bad_3]);
+    }
+}
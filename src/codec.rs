@@ -1,10 +1,17 @@
 use std::{borrow::Borrow, marker::PhantomData, ops::Deref};
 
-use rkyv::{Portable, api::high::HighValidator, bytecheck::CheckBytes, rancor::Error as RkyvError};
+use rkyv::{
+    Archive, Deserialize as RkyvDeserialize, Portable, Serialize as RkyvSerialize,
+    api::high::{HighDeserializer, HighSerializer, HighValidator},
+    bytecheck::CheckBytes,
+    rancor::Error as RkyvError,
+    ser::allocator::ArenaHandle,
+    util::AlignedVec,
+};
 use sled::IVec;
 use thiserror::Error;
 
-use crate::schema::Schema;
+use crate::{encryption::KeyProvider, schema::Schema};
 
 /// Errors that can occur during key/value encoding or decoding.
 #[derive(Debug, Error)]
@@ -28,8 +35,8 @@ pub enum CodecError {
         #[source]
         source: Box<dyn std::error::Error>,
     },
-    /// Value deserialization failed.
-    #[error("failed to deserialize schema '{schema}' value")]
+    /// Key or value deserialization failed.
+    #[error("failed to deserialize schema '{schema}' key or value")]
     DeserializationFailed {
         /// The schema name where the error occurred.
         schema: &'static str,
@@ -37,6 +44,44 @@ pub enum CodecError {
         #[source]
         source: Box<dyn std::error::Error>,
     },
+    /// Stored value does not match its checksum.
+    #[error("checksum mismatch in '{schema}' for key {key:02x?}")]
+    ChecksumMismatch {
+        /// The schema name where the error occurred.
+        schema: &'static str,
+        /// The encoded key of the corrupted value.
+        key: Vec<u8>,
+    },
+    /// Value was written with a version this release cannot decode.
+    #[error("unknown value version {version} in '{schema}'")]
+    UnknownVersion {
+        /// The schema name where the error occurred.
+        schema: &'static str,
+        /// The version tag stored in the value header.
+        version: u32,
+    },
+    /// Value has no key provider to encrypt or decrypt it with.
+    #[error("no key provider configured for encrypted schema '{schema}'")]
+    MissingKeyProvider {
+        /// The schema name where the error occurred.
+        schema: &'static str,
+    },
+    /// Value was encrypted with a key the key provider does not know.
+    #[error("unknown encryption key {key_id} in '{schema}'")]
+    UnknownEncryptionKey {
+        /// The schema name where the error occurred.
+        schema: &'static str,
+        /// The key id stored in the value header.
+        key_id: u32,
+    },
+    /// Value failed authenticated decryption.
+    ///
+    /// The value was tampered with, truncated, or moved from another tree.
+    #[error("failed to decrypt schema '{schema}' value")]
+    DecryptionFailed {
+        /// The schema name where the error occurred.
+        schema: &'static str,
+    },
     /// I/O error during codec operations.
     #[error("io: {0}")]
     IO(#[from] std::io::Error),
@@ -130,6 +175,151 @@ pub trait KeyCodec<S: Schema>: Sized {
     fn encode_key(&self) -> CodecResult<Vec<u8>>;
     /// Decodes the key from bytes.
     fn decode_key(buf: &[u8]) -> CodecResult<Self>;
+
+    /// Stable identifier of the key codec, recorded in the schema registry.
+    ///
+    /// Defaults to the type's full name, see [`Schema::ID`].
+    const CODEC_ID: Option<&'static str> = None;
+
+    /// Appends the key to `buf` as one component of a composite (tuple) key.
+    ///
+    /// Component encodings must be self-delimiting and sort like the keys
+    /// they encode. The default escapes every `0x00` byte of
+    /// [`KeyCodec::encode_key`] as `0x00 0xff` and terminates the component
+    /// with `0x00 0x01`, which is correct for any order-preserving encoding.
+    /// Fixed-width keys override this to store their bytes unchanged.
+    fn encode_key_component(&self, buf: &mut Vec<u8>) -> CodecResult<()> {
+        escape_key_component(&self.encode_key()?, buf);
+        Ok(())
+    }
+
+    /// Decodes one component of a composite key from the front of `buf`.
+    ///
+    /// Returns the decoded key along with the number of bytes consumed. This
+    /// must be the inverse of [`KeyCodec::encode_key_component`].
+    fn decode_key_component(buf: &[u8]) -> CodecResult<(Self, usize)> {
+        let mut raw = Vec::with_capacity(buf.len());
+        let mut pos = 0;
+        loop {
+            match (buf.get(pos), buf.get(pos + 1)) {
+                (Some(0), Some(&ESCAPED_ZERO)) => {
+                    raw.push(0);
+                    pos += 2;
+                }
+                (Some(0), Some(&TERMINATOR)) => return Ok((Self::decode_key(&raw)?, pos + 2)),
+                (Some(&byte), _) if byte != 0 => {
+                    raw.push(byte);
+                    pos += 1;
+                }
+                _ => {
+                    return Err(CodecError::DeserializationFailed {
+                        schema: S::TREE_NAME.0,
+                        source: "unterminated or malformed key component".into(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// Appends `key` to `buf` with the default component encoding of
+/// [`KeyCodec::encode_key_component`].
+fn escape_key_component(key: &[u8], buf: &mut Vec<u8>) {
+    for &byte in key {
+        match byte {
+            0 => buf.extend_from_slice(&[0, ESCAPED_ZERO]),
+            byte => buf.push(byte),
+        }
+    }
+    buf.extend_from_slice(&[0, TERMINATOR]);
+}
+
+/// Trait for the forms a key can be looked up by, such as `str` for
+/// `String` keys.
+///
+/// Lookups such as [`SledTree::get`](crate::SledTree::get) accept any `Q`
+/// that the key type borrows as, like [`HashMap::get`]. Every key type is
+/// its own lookup form. A borrowed form must encode exactly like the owned
+/// key it is borrowed from, both as a whole key and as a key component.
+///
+/// [`HashMap::get`]: std::collections::HashMap::get
+pub trait KeyLookup<S: Schema> {
+    /// Encodes the key like [`KeyCodec::encode_key`].
+    fn encode_lookup_key(&self) -> CodecResult<Vec<u8>>;
+
+    /// Appends the key to `buf` like [`KeyCodec::encode_key_component`].
+    fn encode_lookup_component(&self, buf: &mut Vec<u8>) -> CodecResult<()>;
+}
+
+impl<S: Schema, K: KeyCodec<S>> KeyLookup<S> for K {
+    fn encode_lookup_key(&self) -> CodecResult<Vec<u8>> {
+        self.encode_key()
+    }
+
+    fn encode_lookup_component(&self, buf: &mut Vec<u8>) -> CodecResult<()> {
+        self.encode_key_component(buf)
+    }
+}
+
+/// Looks up `String` keys, which are stored as their raw UTF-8 bytes.
+impl<T: Schema> KeyLookup<T> for str {
+    fn encode_lookup_key(&self) -> CodecResult<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
+    }
+
+    fn encode_lookup_component(&self, buf: &mut Vec<u8>) -> CodecResult<()> {
+        escape_key_component(self.as_bytes(), buf);
+        Ok(())
+    }
+}
+
+/// Byte following `0x00` for an escaped zero inside a key component.
+const ESCAPED_ZERO: u8 = 0xff;
+/// Byte following `0x00` at the end of an escaped key component.
+const TERMINATOR: u8 = 0x01;
+
+/// Database state available to value codecs while encoding and decoding.
+///
+/// Typed trees pass this to [`ValueCodec::encode_value_with`] and
+/// [`ValueCodec::decode_value_with`].
+#[derive(Clone, Copy, Default)]
+pub struct CodecContext<'a> {
+    key_provider: Option<&'a dyn KeyProvider>,
+    key: &'a [u8],
+}
+
+impl<'a> CodecContext<'a> {
+    /// Creates a context with an optional key provider.
+    pub fn new(key_provider: Option<&'a dyn KeyProvider>) -> Self {
+        Self {
+            key_provider,
+            key: &[],
+        }
+    }
+
+    /// Returns this context for the value stored under the encoded `key`.
+    pub fn with_key(self, key: &'a [u8]) -> Self {
+        Self { key, ..self }
+    }
+
+    /// Returns the encoded key of the value, or an empty slice if unknown.
+    pub fn key(&self) -> &'a [u8] {
+        self.key
+    }
+
+    /// Returns the key provider the database was opened with, if any.
+    pub fn key_provider(&self) -> Option<&'a dyn KeyProvider> {
+        self.key_provider
+    }
+}
+
+impl std::fmt::Debug for CodecContext<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CodecContext")
+            .field("has_key_provider", &self.key_provider.is_some())
+            .field("key", &self.key)
+            .finish()
+    }
 }
 
 /// Trait for encoding and decoding values for a specific schema.
@@ -142,6 +332,100 @@ pub trait ValueCodec<S: Schema>: Sized {
 
     /// Decodes the value from the raw bytes stored in sled.
     fn decode_value(buf: IVec) -> CodecResult<Self::Decoded>;
+
+    /// Stable identifier of the value codec, recorded in the schema
+    /// registry.
+    ///
+    /// Defaults to the type's full name, see [`Schema::ID`].
+    const CODEC_ID: Option<&'static str> = None;
+
+    /// Encodes the value with access to the database's [`CodecContext`].
+    ///
+    /// Typed trees always call this method. The default ignores the context;
+    /// codecs that need it, such as `Encrypted`,
+    /// override it, and codecs wrapping another codec forward it.
+    fn encode_value_with(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        let _ = ctx;
+        self.encode_value()
+    }
+
+    /// Decodes the value with access to the database's [`CodecContext`].
+    ///
+    /// See [`ValueCodec::encode_value_with`].
+    fn decode_value_with(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self::Decoded> {
+        let _ = ctx;
+        Self::decode_value(buf)
+    }
+
+    /// Whether encoding the same value always yields the same bytes.
+    ///
+    /// [`SledTree::compare_and_swap`](crate::SledTree::compare_and_swap)
+    /// compares values in their encoded form, except for codecs without a
+    /// stable encoding, such as `Encrypted`, whose values it compares by
+    /// [`ValueCodec::stable_form`]. Codecs wrapping another codec forward it.
+    const STABLE_ENCODING: bool = true;
+
+    /// Returns a form of the stored value `buf` that is equal for equal
+    /// values, even if their stored bytes differ.
+    ///
+    /// Only used when [`ValueCodec::STABLE_ENCODING`] is `false`. The default
+    /// returns the stored bytes.
+    fn stable_form(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<IVec> {
+        let _ = ctx;
+        Ok(buf)
+    }
+
+    /// Encodes the value into the form returned by [`ValueCodec::stable_form`].
+    fn encode_stable_form(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        self.encode_value_with(ctx)
+    }
+}
+
+/// Trait for decoding keys written by the legacy signed-integer layout.
+///
+/// Earlier releases encoded signed integers with plain `to_be_bytes`, which
+/// sorts negative keys after positive ones. This trait is only used by
+/// [`SledDb::migrate_legacy_signed_keys`](crate::SledDb::migrate_legacy_signed_keys)
+/// to rewrite such trees into the current order-preserving layout.
+pub trait LegacySignedKey<S: Schema>: KeyCodec<S> {
+    /// Decodes a key from its legacy (two's complement big-endian) encoding.
+    fn decode_legacy_key(buf: &[u8]) -> CodecResult<Self>;
+}
+
+/// Copies `buf` into a fixed-size array, checking its length against the schema.
+fn fixed_key_bytes<S: Schema, const N: usize>(buf: &[u8]) -> CodecResult<[u8; N]> {
+    buf.try_into().map_err(|_| CodecError::InvalidKeyLength {
+        schema: S::TREE_NAME.0,
+        expected: N,
+        actual: buf.len(),
+    })
+}
+
+/// Decodes a fixed-width key component of `size` bytes from the front of `buf`.
+fn decode_fixed_component<S: Schema, K: KeyCodec<S>>(
+    buf: &[u8],
+    size: usize,
+) -> CodecResult<(K, usize)> {
+    let bytes = buf.get(..size).ok_or(CodecError::InvalidKeyLength {
+        schema: S::TREE_NAME.0,
+        expected: size,
+        actual: buf.len(),
+    })?;
+    Ok((K::decode_key(bytes)?, size))
+}
+
+/// Implements the key component methods for keys with a fixed-width encoding.
+macro_rules! fixed_width_key_component {
+    ($size:expr) => {
+        fn encode_key_component(&self, buf: &mut Vec<u8>) -> CodecResult<()> {
+            buf.extend_from_slice(&KeyCodec::<T>::encode_key(self)?);
+            Ok(())
+        }
+
+        fn decode_key_component(buf: &[u8]) -> CodecResult<(Self, usize)> {
+            decode_fixed_component::<T, Self>(buf, $size)
+        }
+    };
 }
 
 macro_rules! derive_key_codec_for_integers {
@@ -153,19 +437,630 @@ macro_rules! derive_key_codec_for_integers {
 
             fn decode_key(buf: &[u8]) -> CodecResult<Self> {
                 const SIZE: usize = std::mem::size_of::<$int>();
-                if buf.len() != SIZE {
-                    return Err(CodecError::InvalidKeyLength {
-                        schema: T::TREE_NAME.0,
-                        expected: SIZE,
-                        actual: buf.len(),
-                    });
-                }
-                let mut bytes = [0u8; SIZE];
-                bytes.copy_from_slice(buf);
+                let bytes = fixed_key_bytes::<T, SIZE>(buf)?;
+                Ok(<$int>::from_be_bytes(bytes))
+            }
+
+            fixed_width_key_component!(std::mem::size_of::<$int>());
+        })*
+    };
+}
+
+/// Signed integers are encoded big-endian with the sign bit flipped, so that
+/// the byte order of encoded keys matches the numeric order of the integers.
+macro_rules! derive_key_codec_for_signed_integers {
+    ($($int:ty => $uint:ty), *) => {
+        $(impl<T: Schema> KeyCodec<T> for $int {
+            fn encode_key(&self) -> CodecResult<Vec<u8>> {
+                let flipped = (*self as $uint) ^ (1 << (<$uint>::BITS - 1));
+                Ok(flipped.to_be_bytes().into())
+            }
+
+            fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+                const SIZE: usize = std::mem::size_of::<$int>();
+                let bytes = fixed_key_bytes::<T, SIZE>(buf)?;
+                let flipped = <$uint>::from_be_bytes(bytes) ^ (1 << (<$uint>::BITS - 1));
+                Ok(flipped as $int)
+            }
+
+            fixed_width_key_component!(std::mem::size_of::<$int>());
+        }
+
+        impl<T: Schema> LegacySignedKey<T> for $int {
+            fn decode_legacy_key(buf: &[u8]) -> CodecResult<Self> {
+                const SIZE: usize = std::mem::size_of::<$int>();
+                let bytes = fixed_key_bytes::<T, SIZE>(buf)?;
                 Ok(<$int>::from_be_bytes(bytes))
             }
         })*
     };
 }
 
-derive_key_codec_for_integers!(u8, u16, u32, u64, u128, i8, i16, i32, i64, i128);
+derive_key_codec_for_integers!(u8, u16, u32, u64, u128);
+derive_key_codec_for_signed_integers!(i8 => u8, i16 => u16, i32 => u32, i64 => u64, i128 => u128);
+
+/// Strings are stored as their raw UTF-8 bytes, which sort like [`str`].
+impl<T: Schema> KeyCodec<T> for String {
+    fn encode_key(&self) -> CodecResult<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
+    }
+
+    fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+        String::from_utf8(buf.to_vec()).map_err(|e| CodecError::DeserializationFailed {
+            schema: T::TREE_NAME.0,
+            source: Box::new(e),
+        })
+    }
+}
+
+/// Byte vectors are stored verbatim, which sort like `[u8]`.
+impl<T: Schema> KeyCodec<T> for Vec<u8> {
+    fn encode_key(&self) -> CodecResult<Vec<u8>> {
+        Ok(self.clone())
+    }
+
+    fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+        Ok(buf.to_vec())
+    }
+}
+
+/// Fixed-size byte arrays (e.g. hashes) are stored verbatim.
+impl<T: Schema, const N: usize> KeyCodec<T> for [u8; N] {
+    fn encode_key(&self) -> CodecResult<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+
+    fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+        fixed_key_bytes::<T, N>(buf)
+    }
+
+    fixed_width_key_component!(N);
+}
+
+/// Booleans are stored as a single `0` or `1` byte.
+impl<T: Schema> KeyCodec<T> for bool {
+    fn encode_key(&self) -> CodecResult<Vec<u8>> {
+        Ok(vec![u8::from(*self)])
+    }
+
+    fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+        match fixed_key_bytes::<T, 1>(buf)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            [b] => Err(CodecError::DeserializationFailed {
+                schema: T::TREE_NAME.0,
+                source: format!("invalid bool byte {b:#04x}").into(),
+            }),
+        }
+    }
+
+    fixed_width_key_component!(1);
+}
+
+/// Chars are stored as their big-endian `u32` scalar value.
+impl<T: Schema> KeyCodec<T> for char {
+    fn encode_key(&self) -> CodecResult<Vec<u8>> {
+        Ok(u32::from(*self).to_be_bytes().into())
+    }
+
+    fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+        let scalar = u32::from_be_bytes(fixed_key_bytes::<T, 4>(buf)?);
+        char::from_u32(scalar).ok_or_else(|| CodecError::DeserializationFailed {
+            schema: T::TREE_NAME.0,
+            source: format!("invalid char scalar value {scalar:#x}").into(),
+        })
+    }
+
+    fixed_width_key_component!(4);
+}
+
+/// Encodes a composite key as the concatenation of its components.
+///
+/// For keys whose [`KeyCodec::encode_key_component`] writes each field with
+/// its own component encoding, this is a suitable [`KeyCodec::encode_key`].
+pub fn encode_composite_key<S: Schema, K: KeyCodec<S>>(key: &K) -> CodecResult<Vec<u8>> {
+    let mut buf = Vec::new();
+    key.encode_key_component(&mut buf)?;
+    Ok(buf)
+}
+
+/// Decodes a composite key written by [`encode_composite_key`], rejecting
+/// trailing bytes.
+pub fn decode_composite_key<S: Schema, K: KeyCodec<S>>(buf: &[u8]) -> CodecResult<K> {
+    let (key, len) = K::decode_key_component(buf)?;
+    if len != buf.len() {
+        return Err(CodecError::InvalidKeyLength {
+            schema: S::TREE_NAME.0,
+            expected: len,
+            actual: buf.len(),
+        });
+    }
+    Ok(key)
+}
+
+/// Serializes a value with `rkyv`, reporting failures against schema `S`.
+///
+/// This is the encoding half of a typical `rkyv`-backed [`ValueCodec`].
+pub fn encode_rkyv<S, T>(value: &T) -> CodecResult<Vec<u8>>
+where
+    S: Schema,
+    T: for<'a> RkyvSerialize<HighSerializer<AlignedVec, ArenaHandle<'a>, RkyvError>>,
+{
+    rkyv::to_bytes::<RkyvError>(value)
+        .map(|bytes| bytes.into_vec())
+        .map_err(|e| CodecError::SerializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        })
+}
+
+/// Deserializes an owned value from `rkyv` bytes stored in sled, reporting
+/// failures against schema `S`.
+///
+/// The bytes are copied into an [`AlignedVec`] first since [`IVec`] gives no
+/// alignment guarantees.
+pub fn decode_rkyv<S, T>(buf: IVec) -> CodecResult<T>
+where
+    S: Schema,
+    T: Archive,
+    T::Archived: for<'a> CheckBytes<HighValidator<'a, RkyvError>>
+        + RkyvDeserialize<T, HighDeserializer<RkyvError>>,
+{
+    let mut aligned = AlignedVec::<16>::with_capacity(buf.len());
+    aligned.extend_from_slice(buf.as_ref());
+    rkyv::from_bytes::<T, RkyvError>(&aligned).map_err(|e| CodecError::DeserializationFailed {
+        schema: S::TREE_NAME.0,
+        source: e.into(),
+    })
+}
+
+/// Validates `rkyv` bytes stored in sled and returns a zero-copy view over
+/// them, reporting failures against schema `S`.
+pub fn decode_rkyv_view<S, T>(buf: IVec) -> CodecResult<RkyvView<AlignedVec, T::Archived>>
+where
+    S: Schema,
+    T: Archive,
+    T::Archived: for<'a> CheckBytes<HighValidator<'a, RkyvError>>,
+{
+    let mut aligned = AlignedVec::with_capacity(buf.len());
+    aligned.extend_from_slice(buf.as_ref());
+    RkyvView::try_new(aligned).map_err(|e| CodecError::DeserializationFailed {
+        schema: S::TREE_NAME.0,
+        source: e.into(),
+    })
+}
+
+/// Decoding mode for [`Rkyv`] that deserializes values into an owned `T`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Owned;
+
+/// Decoding mode for [`Rkyv`] that validates values and returns a zero-copy
+/// [`RkyvView`] over the archived bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ZeroCopy;
+
+/// Generic `rkyv` value codec for any `T: Archive + Serialize`.
+///
+/// Use `Rkyv<T>` (or `Rkyv<T, Owned>`) as a schema's value type to decode
+/// values into `T`, or `Rkyv<T, ZeroCopy>` to decode into an
+/// `RkyvView<AlignedVec, T::Archived>` without deserializing. Codec errors
+/// are attributed to the schema automatically.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rkyv<T, M = Owned> {
+    value: T,
+    _mode: PhantomData<fn() -> M>,
+}
+
+impl<T, M> Rkyv<T, M> {
+    /// Wraps a value for storage.
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T, M> From<T> for Rkyv<T, M> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<T, M> Deref for Rkyv<T, M> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<S, T> ValueCodec<S> for Rkyv<T, Owned>
+where
+    S: Schema,
+    T: Archive + for<'a> RkyvSerialize<HighSerializer<AlignedVec, ArenaHandle<'a>, RkyvError>>,
+    T::Archived: for<'a> CheckBytes<HighValidator<'a, RkyvError>>
+        + RkyvDeserialize<T, HighDeserializer<RkyvError>>,
+{
+    type Decoded = T;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        encode_rkyv::<S, T>(&self.value)
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        decode_rkyv::<S, T>(buf)
+    }
+}
+
+impl<S, T> ValueCodec<S> for Rkyv<T, ZeroCopy>
+where
+    S: Schema,
+    T: Archive + for<'a> RkyvSerialize<HighSerializer<AlignedVec, ArenaHandle<'a>, RkyvError>>,
+    T::Archived: for<'a> CheckBytes<HighValidator<'a, RkyvError>>,
+{
+    type Decoded = RkyvView<AlignedVec, T::Archived>;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        encode_rkyv::<S, T>(&self.value)
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        decode_rkyv_view::<S, T>(buf)
+    }
+}
+
+/// Implements [`KeyCodec`] for tuples by concatenating the component
+/// encodings of their elements, so tuples sort lexicographically by element
+/// and a tuple's encoding is a prefix of every longer tuple sharing its
+/// leading elements.
+macro_rules! impl_key_codec_for_tuple {
+    ($(($idx:tt, $name:ident, $var:ident)),+) => {
+        impl<S: Schema, $($name: KeyCodec<S>),+> KeyCodec<S> for ($($name,)+) {
+            fn encode_key(&self) -> CodecResult<Vec<u8>> {
+                encode_composite_key::<S, Self>(self)
+            }
+
+            fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+                decode_composite_key::<S, Self>(buf)
+            }
+
+            fn encode_key_component(&self, buf: &mut Vec<u8>) -> CodecResult<()> {
+                $(self.$idx.encode_key_component(buf)?;)+
+                Ok(())
+            }
+
+            fn decode_key_component(buf: &[u8]) -> CodecResult<(Self, usize)> {
+                let mut pos = 0;
+                $(
+                    let ($var, len) = $name::decode_key_component(&buf[pos..])?;
+                    pos += len;
+                )+
+                Ok((($($var,)+), pos))
+            }
+        }
+    };
+}
+
+impl_key_codec_for_tuple!((0, A0, a0));
+impl_key_codec_for_tuple!((0, A0, a0), (1, A1, a1));
+impl_key_codec_for_tuple!((0, A0, a0), (1, A1, a1), (2, A2, a2));
+impl_key_codec_for_tuple!((0, A0, a0), (1, A1, a1), (2, A2, a2), (3, A3, a3));
+impl_key_codec_for_tuple!(
+    (0, A0, a0),
+    (1, A1, a1),
+    (2, A2, a2),
+    (3, A3, a3),
+    (4, A4, a4)
+);
+impl_key_codec_for_tuple!(
+    (0, A0, a0),
+    (1, A1, a1),
+    (2, A2, a2),
+    (3, A3, a3),
+    (4, A4, a4),
+    (5, A5, a5)
+);
+
+/// A typed leading portion of a composite key, e.g. `Prefix((account_id,))`
+/// for keys of type `(u32, u64)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Prefix<P>(pub P);
+
+/// Trait for typed prefixes of keys of type `K`.
+///
+/// Used by [`SledTree::scan_prefix`](crate::SledTree::scan_prefix). The
+/// encoded prefix must be a byte prefix of the encoding of every key it
+/// matches.
+pub trait KeyPrefix<S: Schema, K> {
+    /// Encodes the prefix into the leading bytes shared by all matching keys.
+    fn encode_prefix(&self) -> CodecResult<Vec<u8>>;
+}
+
+impl<T: Schema> KeyPrefix<T, String> for str {
+    fn encode_prefix(&self) -> CodecResult<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
+    }
+}
+
+impl<T: Schema> KeyPrefix<T, String> for String {
+    fn encode_prefix(&self) -> CodecResult<Vec<u8>> {
+        KeyPrefix::<T, String>::encode_prefix(self.as_str())
+    }
+}
+
+impl<T: Schema> KeyPrefix<T, Vec<u8>> for [u8] {
+    fn encode_prefix(&self) -> CodecResult<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+}
+
+impl<T: Schema> KeyPrefix<T, Vec<u8>> for Vec<u8> {
+    fn encode_prefix(&self) -> CodecResult<Vec<u8>> {
+        KeyPrefix::<T, Vec<u8>>::encode_prefix(self.as_slice())
+    }
+}
+
+/// Implements [`KeyPrefix`] for a [`Prefix`] of the leading tuple elements.
+macro_rules! impl_key_prefix {
+    ([$(($idx:tt, $name:ident))+] [$(($rest_idx:tt, $rest:ident))*]) => {
+        impl<S: Schema, $($name: KeyCodec<S>,)+ $($rest,)*> KeyPrefix<S, ($($name,)+ $($rest,)*)>
+            for Prefix<($($name,)+)>
+        {
+            fn encode_prefix(&self) -> CodecResult<Vec<u8>> {
+                let mut buf = Vec::new();
+                $(self.0.$idx.encode_key_component(&mut buf)?;)+
+                Ok(buf)
+            }
+        }
+    };
+}
+
+/// Implements [`KeyPrefix`] for every non-empty prefix of a tuple key.
+macro_rules! impl_key_prefixes {
+    ([$($prefix:tt)*] [$next:tt $($rest:tt)*]) => {
+        impl_key_prefix!([$($prefix)* $next] [$($rest)*]);
+        impl_key_prefixes!([$($prefix)* $next] [$($rest)*]);
+    };
+    ([$($prefix:tt)*] []) => {};
+}
+
+impl_key_prefixes!([][(0, A0)]);
+impl_key_prefixes!([][(0, A0)(1, A1)]);
+impl_key_prefixes!([][(0, A0)(1, A1)(2, A2)]);
+impl_key_prefixes!([][(0, A0)(1, A1)(2, A2)(3, A3)]);
+impl_key_prefixes!([][(0, A0)(1, A1)(2, A2)(3, A3)(4, A4)]);
+impl_key_prefixes!([][(0, A0)(1, A1)(2, A2)(3, A3)(4, A4)(5, A5)]);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    fn encode<K: KeyCodec<TestSchema1>>(key: K) -> Vec<u8> {
+        key.encode_key().unwrap()
+    }
+
+    #[test]
+    fn test_signed_key_roundtrip() {
+        for key in [i64::MIN, -1_000, -1, 0, 1, 1_000, i64::MAX] {
+            let bytes = encode(key);
+            assert_eq!(bytes.len(), 8);
+            assert_eq!(
+                <i64 as KeyCodec<TestSchema1>>::decode_key(&bytes).unwrap(),
+                key
+            );
+        }
+
+        for key in [i8::MIN, -1, 0, 1, i8::MAX] {
+            let bytes = encode(key);
+            assert_eq!(
+                <i8 as KeyCodec<TestSchema1>>::decode_key(&bytes).unwrap(),
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn test_signed_key_encoding_preserves_order() {
+        let keys = [i32::MIN, -70_000, -256, -1, 0, 1, 255, 70_000, i32::MAX];
+        let encoded: Vec<_> = keys.iter().map(|k| encode(*k)).collect();
+
+        let mut sorted = encoded.clone();
+        sorted.sort();
+        assert_eq!(encoded, sorted);
+    }
+
+    #[test]
+    fn test_legacy_signed_key_decoding() {
+        let legacy = (-5_i16).to_be_bytes();
+        let key = <i16 as LegacySignedKey<TestSchema1>>::decode_legacy_key(&legacy).unwrap();
+        assert_eq!(key, -5);
+    }
+
+    fn assert_sorted<K: KeyCodec<TestSchema1> + Clone + Ord + std::fmt::Debug>(keys: &[K]) {
+        let mut expected = keys.to_vec();
+        expected.sort();
+
+        let mut encoded: Vec<_> = keys.iter().map(|k| encode(k.clone())).collect();
+        encoded.sort();
+        let decoded: Vec<K> = encoded
+            .iter()
+            .map(|bytes| K::decode_key(bytes).unwrap())
+            .collect();
+        assert_eq!(decoded, expected);
+    }
+
+    #[test]
+    fn test_builtin_keys_preserve_order() {
+        assert_sorted(&["b", "", "ab", "a", "é", "z"].map(String::from));
+        assert_sorted(&[vec![2], vec![], vec![1, 255], vec![1], vec![0, 0]]);
+        assert_sorted(&[[3_u8; 32], [0; 32], [255; 32], [1; 32]]);
+        assert_sorted(&[true, false]);
+        assert_sorted(&['z', 'a', '\0', 'é', '😀', 'A']);
+    }
+
+    #[test]
+    fn test_tuple_keys_preserve_order() {
+        assert_sorted(&[(2_u32, 1_u64), (1, u64::MAX), (1, 0), (0, 5)]);
+        assert_sorted(&[(-1_i32, 3_u8), (-1, 0), (i32::MIN, 9), (7, 0)]);
+        assert_sorted(
+            &[
+                ("a", 2),
+                ("ab", 0),
+                ("a", 1),
+                ("", 9),
+                ("a\0", 0),
+                ("a\0\0", 0),
+                ("b", 0),
+            ]
+            .map(|(s, n)| (s.to_string(), n as u64)),
+        );
+        assert_sorted(&[
+            (vec![0_u8], 1_u16, true),
+            (vec![], 2, false),
+            (vec![0, 0], 0, true),
+            (vec![0], 1, false),
+        ]);
+        assert_sorted(&[
+            (1_u8, 2_u8, 3_u8, 4_u8, 5_u8, 'b'),
+            (1, 2, 3, 4, 5, 'a'),
+            (0, 9, 9, 9, 9, 'z'),
+        ]);
+    }
+
+    #[test]
+    fn test_tuple_key_encoding_is_prefix_of_longer_tuple() {
+        let short = encode(("acct".to_string(),));
+        let long = encode(("acct".to_string(), 42_u64));
+        assert!(long.starts_with(&short));
+
+        let short = encode((7_u32, -1_i64));
+        let long = encode((7_u32, -1_i64, "x".to_string()));
+        assert!(long.starts_with(&short));
+    }
+
+    #[test]
+    fn test_prefix_encoding_matches_key_encoding() {
+        type Key = (u32, String, i64);
+        let key: Key = (7, "acct".to_string(), -3);
+        let encoded = encode(key.clone());
+
+        let prefix = KeyPrefix::<TestSchema1, Key>::encode_prefix(&Prefix((7_u32,))).unwrap();
+        assert!(encoded.starts_with(&prefix));
+        let prefix =
+            KeyPrefix::<TestSchema1, Key>::encode_prefix(&Prefix((7_u32, "acct".to_string())))
+                .unwrap();
+        assert!(encoded.starts_with(&prefix));
+        let prefix = KeyPrefix::<TestSchema1, Key>::encode_prefix(&Prefix(key)).unwrap();
+        assert_eq!(prefix, encoded);
+
+        let prefix = KeyPrefix::<TestSchema1, String>::encode_prefix("ac").unwrap();
+        assert!(encode("acct".to_string()).starts_with(&prefix));
+    }
+
+    #[test]
+    fn test_nested_tuple_key_roundtrip() {
+        let key = (("ns".to_string(), 1_u32), vec![0_u8, 0xff, 0], 9_i16);
+        let bytes = encode(key.clone());
+        let decoded = <_ as KeyCodec<TestSchema1>>::decode_key(&bytes).unwrap();
+        assert_eq!(key, decoded);
+    }
+
+    #[test]
+    fn test_tuple_key_rejects_malformed_bytes() {
+        type Key = (String, u32);
+
+        let mut bytes = encode(("a".to_string(), 1_u32));
+        bytes.push(0);
+        let err = <Key as KeyCodec<TestSchema1>>::decode_key(&bytes).unwrap_err();
+        assert!(matches!(err, CodecError::InvalidKeyLength { .. }));
+
+        let err = <Key as KeyCodec<TestSchema1>>::decode_key(b"abc").unwrap_err();
+        assert!(matches!(err, CodecError::DeserializationFailed { .. }));
+
+        let err = <Key as KeyCodec<TestSchema1>>::decode_key(&[b'a', 0, 1, 0]).unwrap_err();
+        assert!(matches!(
+            err,
+            CodecError::InvalidKeyLength {
+                expected: 4,
+                actual: 1,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_string_key_rejects_invalid_utf8() {
+        let err = <String as KeyCodec<TestSchema1>>::decode_key(&[0xff, 0xfe]).unwrap_err();
+        assert!(matches!(
+            err,
+            CodecError::DeserializationFailed { schema, .. } if schema == TestSchema1::TREE_NAME.0
+        ));
+    }
+
+    #[test]
+    fn test_fixed_size_keys_reject_invalid_length() {
+        let err = <[u8; 32] as KeyCodec<TestSchema1>>::decode_key(&[0; 31]).unwrap_err();
+        assert!(matches!(
+            err,
+            CodecError::InvalidKeyLength {
+                schema,
+                expected: 32,
+                actual: 31,
+            } if schema == TestSchema1::TREE_NAME.0
+        ));
+
+        let err = <bool as KeyCodec<TestSchema1>>::decode_key(&[]).unwrap_err();
+        assert!(matches!(
+            err,
+            CodecError::InvalidKeyLength {
+                expected: 1,
+                actual: 0,
+                ..
+            }
+        ));
+
+        let err = <char as KeyCodec<TestSchema1>>::decode_key(&[0, 0]).unwrap_err();
+        assert!(matches!(
+            err,
+            CodecError::InvalidKeyLength {
+                expected: 4,
+                actual: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_bool_and_char_keys_reject_invalid_values() {
+        let err = <bool as KeyCodec<TestSchema1>>::decode_key(&[2]).unwrap_err();
+        assert!(matches!(err, CodecError::DeserializationFailed { .. }));
+
+        let surrogate = 0xD800_u32.to_be_bytes();
+        let err = <char as KeyCodec<TestSchema1>>::decode_key(&surrogate).unwrap_err();
+        assert!(matches!(err, CodecError::DeserializationFailed { .. }));
+    }
+
+    #[test]
+    fn test_integer_key_invalid_length() {
+        let err = <i32 as KeyCodec<TestSchema1>>::decode_key(&[0, 1]).unwrap_err();
+        match err {
+            CodecError::InvalidKeyLength {
+                schema,
+                expected,
+                actual,
+            } => {
+                assert_eq!(schema, TestSchema1::TREE_NAME.0);
+                assert_eq!(expected, 4);
+                assert_eq!(actual, 2);
+            }
+            other => panic!("expected InvalidKeyLength, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use std::{marker::PhantomData, ops::Deref};
+
+use sled::IVec;
+
+use crate::{CodecError, CodecResult, Schema, ValueCodec, codec::CodecContext};
+
+/// Header byte of values stored without compression.
+pub const RAW_HEADER: u8 = 0;
+
+/// Default size in bytes below which [`Compressed`] stores values raw.
+pub const DEFAULT_THRESHOLD: usize = 256;
+
+/// A compression algorithm usable with [`Compressed`].
+pub trait Compression {
+    /// Header byte identifying values compressed with this algorithm.
+    ///
+    /// Must not be [`RAW_HEADER`] and must never change once data is written.
+    const ID: u8;
+
+    /// Compresses `data`.
+    fn compress(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+
+    /// Decompresses data produced by [`Compression::compress`].
+    fn decompress(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>>;
+}
+
+/// LZ4 compression via the pure-Rust `lz4_flex` crate.
+#[cfg(feature = "lz4")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Lz4;
+
+#[cfg(feature = "lz4")]
+impl Compression for Lz4 {
+    const ID: u8 = 1;
+
+    fn compress(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        Ok(lz4_flex::compress_prepend_size(data))
+    }
+
+    /// Rejects a declared size that the block could not expand to before
+    /// allocating, so a corrupted prefix cannot request up to 4 GiB.
+    fn decompress(data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let (prefix, block) = data
+            .split_first_chunk::<4>()
+            .ok_or("missing LZ4 size prefix")?;
+        let size = u32::from_le_bytes(*prefix) as usize;
+        if size > block.len().saturating_mul(LZ4_MAX_RATIO) {
+            return Err(format!(
+                "declared LZ4 size {size} exceeds what a {}-byte block can hold",
+                block.len()
+            )
+            .into());
+        }
+        Ok(lz4_flex::block::decompress(block, size)?)
+    }
+}
+
+/// Upper bound on how much an LZ4 block can expand: a match of `n` bytes
+/// takes at least `n / 255` bytes to encode.
+#[cfg(feature = "lz4")]
+const LZ4_MAX_RATIO: usize = 255;
+
+/// Value codec combinator that compresses the output of codec `C` with
+/// algorithm `A`.
+///
+/// Every stored value starts with a header byte: [`RAW_HEADER`] for values
+/// stored as-is, or [`Compression::ID`] for compressed ones. Values whose
+/// encoding is shorter than `THRESHOLD` bytes, or that do not shrink, are
+/// stored raw. Decoding yields `C::Decoded`, so `Compressed<Rkyv<T>, Lz4>`
+/// decodes into `T`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Compressed<C, A, const THRESHOLD: usize = DEFAULT_THRESHOLD> {
+    value: C,
+    _algo: PhantomData<fn() -> A>,
+}
+
+impl<C, A, const THRESHOLD: usize> Compressed<C, A, THRESHOLD> {
+    /// Wraps a value for storage.
+    pub fn new(value: C) -> Self {
+        Self {
+            value,
+            _algo: PhantomData,
+        }
+    }
+
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> C {
+        self.value
+    }
+}
+
+impl<C, A, const THRESHOLD: usize> From<C> for Compressed<C, A, THRESHOLD> {
+    fn from(value: C) -> Self {
+        Self::new(value)
+    }
+}
+
+impl<C, A, const THRESHOLD: usize> Deref for Compressed<C, A, THRESHOLD> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.value
+    }
+}
+
+impl<S, C, A, const THRESHOLD: usize> ValueCodec<S> for Compressed<C, A, THRESHOLD>
+where
+    S: Schema,
+    C: ValueCodec<S>,
+    A: Compression,
+{
+    type Decoded = C::Decoded;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        self.encode_value_with(&CodecContext::default())
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        Self::decode_value_with(buf, &CodecContext::default())
+    }
+
+    fn encode_value_with(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        let raw = self.value.encode_value_with(ctx)?;
+        if raw.len() >= THRESHOLD {
+            let compressed =
+                A::compress(&raw).map_err(|source| CodecError::SerializationFailed {
+                    schema: S::TREE_NAME.0,
+                    source,
+                })?;
+            if compressed.len() < raw.len() {
+                return Ok(with_header(A::ID, &compressed));
+            }
+        }
+        Ok(with_header(RAW_HEADER, &raw))
+    }
+
+    fn decode_value_with(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self::Decoded> {
+        C::decode_value_with(decompress::<S, A>(buf)?, ctx)
+    }
+
+    const STABLE_ENCODING: bool = C::STABLE_ENCODING;
+
+    fn stable_form(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<IVec> {
+        C::stable_form(decompress::<S, A>(buf)?, ctx)
+    }
+
+    fn encode_stable_form(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        self.value.encode_stable_form(ctx)
+    }
+}
+
+/// Strips the header of a value written by [`Compressed`] and decompresses
+/// its body if needed.
+fn decompress<S: Schema, A: Compression>(buf: IVec) -> CodecResult<IVec> {
+    let Some(&header) = buf.first() else {
+        return Err(CodecError::DeserializationFailed {
+            schema: S::TREE_NAME.0,
+            source: "missing compression header".into(),
+        });
+    };
+    let body = buf.subslice(1, buf.len() - 1);
+    match header {
+        RAW_HEADER => Ok(body),
+        id if id == A::ID => {
+            let raw = A::decompress(&body).map_err(|source| CodecError::DeserializationFailed {
+                schema: S::TREE_NAME.0,
+                source,
+            })?;
+            Ok(raw.into())
+        }
+        id => Err(CodecError::DeserializationFailed {
+            schema: S::TREE_NAME.0,
+            source: format!("unknown compression header {id:#04x}").into(),
+        }),
+    }
+}
+
+/// Prefixes `body` with a header byte.
+fn with_header(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(body.len() + 1);
+    buf.push(header);
+    buf.extend_from_slice(body);
+    buf
+}
+
+#[cfg(all(test, feature = "lz4"))]
+mod tests {
+    use super::*;
+    use crate::{KeyCodec, Rkyv, SledTree, TreeName, error::Error, test_utils::*};
+
+    #[derive(Debug)]
+    struct CompressedSchema;
+
+    impl Schema for CompressedSchema {
+        const TREE_NAME: TreeName = TreeName("compressed");
+        type Key = u32;
+        type Value = Compressed<Rkyv<TestValue>, Lz4, 64>;
+    }
+
+    fn create_compressed_tree() -> SledTree<CompressedSchema> {
+        create_temp_tree::<CompressedSchema>().unwrap()
+    }
+
+    fn raw_value(tree: &SledTree<CompressedSchema>, key: u32) -> IVec {
+        let key = <u32 as KeyCodec<CompressedSchema>>::encode_key(&key).unwrap();
+        tree.inner.get(key).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_small_values_are_stored_raw() {
+        let tree = create_compressed_tree();
+
+        tree.insert(&1, &Rkyv::new(TestValue::alice()).into())
+            .unwrap();
+
+        assert_eq!(raw_value(&tree, 1)[0], RAW_HEADER);
+        assert_test_values_eq(&tree.get(&1).unwrap().unwrap(), &TestValue::alice());
+    }
+
+    #[test]
+    fn test_large_values_are_compressed() {
+        let tree = create_compressed_tree();
+        let value = TestValue::new(7, &"repetitive ".repeat(100));
+
+        tree.insert(&1, &Rkyv::new(value.clone()).into()).unwrap();
+
+        let raw = raw_value(&tree, 1);
+        assert_eq!(raw[0], Lz4::ID);
+        assert!(raw.len() < value.name.len());
+        assert_test_values_eq(&tree.get(&1).unwrap().unwrap(), &value);
+    }
+
+    #[test]
+    fn test_incompressible_values_are_stored_raw() {
+        let tree = create_compressed_tree();
+        // A pseudo-random name that LZ4 cannot shrink.
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let name: String = (0..200)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                char::from(b'!' + (state % 90) as u8)
+            })
+            .collect();
+        let value = TestValue::new(9, &name);
+
+        tree.insert(&1, &Rkyv::new(value.clone()).into()).unwrap();
+
+        assert_eq!(raw_value(&tree, 1)[0], RAW_HEADER);
+        assert_test_values_eq(&tree.get(&1).unwrap().unwrap(), &value);
+    }
+
+    #[test]
+    fn test_unknown_header_is_rejected() {
+        let tree = create_compressed_tree();
+        let key = <u32 as KeyCodec<CompressedSchema>>::encode_key(&1).unwrap();
+        tree.inner.insert(key, vec![0x7f_u8, 1, 2, 3]).unwrap();
+
+        match tree.get(&1) {
+            Err(Error::CodecError(CodecError::DeserializationFailed { schema, source })) => {
+                assert_eq!(schema, CompressedSchema::TREE_NAME.0);
+                assert!(source.to_string().contains("unknown compression header"));
+            }
+            other => panic!("expected unknown header error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_corrupted_compressed_value_is_rejected() {
+        let tree = create_compressed_tree();
+        let key = <u32 as KeyCodec<CompressedSchema>>::encode_key(&1).unwrap();
+        tree.inner
+            .insert(key, vec![Lz4::ID, 0xff, 0xff, 0xff, 0x0f, 1])
+            .unwrap();
+
+        match tree.get(&1) {
+            Err(Error::CodecError(CodecError::DeserializationFailed { schema, .. })) => {
+                assert_eq!(schema, CompressedSchema::TREE_NAME.0)
+            }
+            other => panic!("expected deserialization error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_oversized_declared_length_is_rejected() {
+        let tree = create_compressed_tree();
+        let key = <u32 as KeyCodec<CompressedSchema>>::encode_key(&1).unwrap();
+        let mut value = vec![Lz4::ID];
+        value.extend_from_slice(&u32::MAX.to_le_bytes());
+        value.extend_from_slice(&[0x10, b'a']);
+        tree.inner.insert(key, value).unwrap();
+
+        match tree.get(&1) {
+            Err(Error::CodecError(CodecError::DeserializationFailed { source, .. })) => {
+                assert!(source.to_string().contains("declared LZ4 size"))
+            }
+            other => panic!("expected deserialization error, got {other:?}"),
+        }
+    }
+}
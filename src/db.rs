@@ -1,49 +1,438 @@
+use std::{path::Path, sync::Arc};
+
 use dashmap::DashMap;
-use sled::{Db, Tree};
+use sled::{Config, Db, Mode, Tree};
 
+#[cfg(feature = "checksum")]
+use crate::checksum::ChecksummedValue;
 use crate::{
-    error::Result,
+    codec::LegacySignedKey,
+    encryption::KeyProvider,
+    error::{Error, Result},
+    flush::{FlushPolicy, Flusher},
+    index::{IndexedTree, TreeIndexes},
+    migration::{self, Migration},
+    registry::{self, RESERVED_PREFIX},
     schema::{Schema, TreeName},
     tree::SledTree,
+    versioned::UpgradableValue,
 };
 
+/// Reserved tree recording the trees whose legacy signed keys were migrated.
+const LEGACY_KEYS_TREE: &str = "__typed_sled_legacy_keys";
+
+/// A tree opened through a [`SledDb`].
+#[derive(Debug)]
+struct OpenTree {
+    tree: Tree,
+    /// Flush state shared by all handles of the tree.
+    flusher: Arc<Flusher>,
+    /// Identifier of the schema the tree was opened with.
+    schema: &'static str,
+    /// Indexes attached to the tree, shared by all handles of the tree.
+    indexes: Arc<TreeIndexes>,
+}
+
 /// A type-safe wrapper around sled database with schema-based tree management.
+///
+/// Every tree is registered to the schema that first opens it, in a reserved
+/// metadata tree. Opening it with another schema type, or after its codecs
+/// or [`Schema::VERSION`] changed, fails instead of misreading its data.
 #[derive(Debug)]
 pub struct SledDb {
-    /// Mapping of treenames to sled tree.
-    inner_trees: DashMap<TreeName, Tree>,
+    /// Mapping of treenames to opened trees.
+    inner_trees: DashMap<TreeName, OpenTree>,
     /// The actual sled db.
     inner_db: Db,
+    /// Source of encryption keys for encrypted values.
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    /// Flush policy of trees opened through this database.
+    flush_policy: FlushPolicy,
 }
 
 impl SledDb {
+    /// Returns a builder that opens a database from typed configuration.
+    pub fn builder() -> SledDbBuilder {
+        SledDbBuilder::default()
+    }
+
     /// Creates a new typed sled database wrapper.
     pub fn new(inner_db: Db) -> Result<Self> {
         Ok(Self {
             inner_db,
             inner_trees: DashMap::new(),
+            key_provider: None,
+            flush_policy: FlushPolicy::default(),
+        })
+    }
+
+    /// Creates a typed sled database wrapper that encrypts and decrypts
+    /// `Encrypted` values with keys from `key_provider`.
+    pub fn with_key_provider(
+        inner_db: Db,
+        key_provider: impl KeyProvider + 'static,
+    ) -> Result<Self> {
+        Ok(Self {
+            inner_db,
+            inner_trees: DashMap::new(),
+            key_provider: Some(Arc::new(key_provider)),
+            flush_policy: FlushPolicy::default(),
         })
     }
 
+    /// Sets the flush policy of every tree opened through this database.
+    ///
+    /// Defaults to [`FlushPolicy::Always`]. Use
+    /// [`SledTree::with_flush_policy`] to override it for a single tree.
+    pub fn with_flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        for mut entry in self.inner_trees.iter_mut() {
+            entry.flusher = Arc::new(Flusher::new(policy));
+        }
+        self
+    }
+
+    /// Asynchronously flushes the database's pending writes to disk,
+    /// returning the number of bytes flushed.
+    #[cfg(feature = "async")]
+    pub async fn flush_async(&self) -> Result<usize> {
+        Ok(self.inner_db.flush_async().await?)
+    }
+
     /// Gets or creates a typed tree for the given schema.
+    ///
+    /// Fails if the tree is registered to a different schema, see [`SledDb`].
+    /// Writes through the tree maintain the indexes attached to it, see
+    /// [`IndexedTree`].
     pub fn get_tree<S: Schema>(&self) -> Result<SledTree<S>> {
-        if let Some(tree) = self.inner_trees.get(&S::TREE_NAME) {
-            return Ok(SledTree::new(tree.clone()));
+        if let Some(entry) = self.inner_trees.get(&S::TREE_NAME) {
+            return self.typed_tree(entry.value());
         }
 
         // Create the tree
         let tree_name = S::TREE_NAME.into_inner();
+        if tree_name.starts_with(RESERVED_PREFIX) {
+            return Err(Error::ReservedTreeName(tree_name));
+        }
+        registry::check_or_register::<S>(&self.inner_db)?;
         let tree = self.inner_db.open_tree(tree_name)?;
+        let indexes = registry::indexes_of(&self.inner_db, tree_name)?;
 
         let entry = self.inner_trees.entry(S::TREE_NAME);
-        let final_tree = entry.or_insert(tree);
-        Ok(SledTree::new(final_tree.clone()))
+        let final_tree = entry.or_insert_with(|| OpenTree {
+            tree,
+            flusher: Arc::new(Flusher::new(self.flush_policy)),
+            schema: registry::schema_id::<S>(),
+            indexes: Arc::new(TreeIndexes::recorded(indexes)),
+        });
+        self.typed_tree(final_tree.value())
+    }
+
+    /// Gets or creates a typed tree for the given schema whose writes
+    /// maintain secondary indexes, attached with [`IndexedTree::with_index`].
+    pub fn get_indexed_tree<S: Schema>(&self) -> Result<IndexedTree<S>> {
+        Ok(IndexedTree::new(
+            self.get_tree::<S>()?,
+            self.inner_db.clone(),
+        ))
+    }
+
+    /// Registers schema `S` for its tree, replacing the previous registration.
+    ///
+    /// Use this after intentionally changing a schema, such as bumping
+    /// [`Schema::VERSION`] once the tree's data has been converted.
+    pub fn register_schema<S: Schema>(&self) -> Result<()> {
+        let tree_name = S::TREE_NAME.into_inner();
+        if tree_name.starts_with(RESERVED_PREFIX) {
+            return Err(Error::ReservedTreeName(tree_name));
+        }
+        registry::register::<S>(&self.inner_db)?;
+        self.registered::<S>();
+        Ok(())
+    }
+
+    /// Updates the open tree of schema `S` after `S` was registered for it.
+    pub(crate) fn registered<S: Schema>(&self) {
+        if let Some(mut entry) = self.inner_trees.get_mut(&S::TREE_NAME) {
+            entry.schema = registry::schema_id::<S>();
+        }
+    }
+
+    /// Forgets the indexes of the open tree of schema `S` after they were
+    /// detached from it.
+    pub(crate) fn detached_indexes<S: Schema>(&self) {
+        if let Some(entry) = self.inner_trees.get(&S::TREE_NAME) {
+            entry.indexes.detach_all();
+        }
+    }
+
+    /// Runs the steps of `migrations` that have not been applied yet, in
+    /// order, and returns the number of steps run.
+    ///
+    /// `migrations` lists every step in increasing version order, including
+    /// those already applied. The applied version is recorded in a reserved
+    /// metadata tree after each step. Fails without running anything if the
+    /// list is out of order or the database was migrated past its last step.
+    pub fn run_migrations(&self, migrations: &[Migration]) -> Result<usize> {
+        migration::run(self, migrations)
+    }
+
+    /// Returns the version of the last migration step applied to this
+    /// database, if any.
+    pub fn migration_version(&self) -> Result<Option<u64>> {
+        migration::current_version(self)
+    }
+
+    /// Opens the tree of schema `S` without checking the schema registry.
+    pub(crate) fn open_unchecked<S: Schema>(&self) -> Result<SledTree<S>> {
+        let tree_name = S::TREE_NAME.into_inner();
+        if tree_name.starts_with(RESERVED_PREFIX) {
+            return Err(Error::ReservedTreeName(tree_name));
+        }
+        if let Some(entry) = self.inner_trees.get(&S::TREE_NAME) {
+            return Ok(SledTree::from_parts(
+                entry.tree.clone(),
+                self.key_provider.clone(),
+                entry.flusher.clone(),
+                entry.indexes.clone(),
+            ));
+        }
+        let indexes = registry::indexes_of(&self.inner_db, tree_name)?;
+        Ok(SledTree::from_parts(
+            self.inner_db.open_tree(tree_name)?,
+            self.key_provider.clone(),
+            Arc::new(Flusher::new(self.flush_policy)),
+            Arc::new(TreeIndexes::recorded(indexes)),
+        ))
+    }
+
+    /// Deletes the tree of schema `S` and its registration, returning
+    /// whether the tree existed.
+    pub(crate) fn drop_tree<S: Schema>(&self) -> Result<bool> {
+        let tree_name = S::TREE_NAME.into_inner();
+        if tree_name.starts_with(RESERVED_PREFIX) {
+            return Err(Error::ReservedTreeName(tree_name));
+        }
+        self.inner_trees.remove(&S::TREE_NAME);
+        registry::unregister(&self.inner_db, tree_name)?;
+        Ok(self.inner_db.drop_tree(tree_name)?)
+    }
+
+    /// Returns the underlying sled database.
+    pub(crate) fn inner_db(&self) -> &Db {
+        &self.inner_db
+    }
+
+    /// Wraps an opened tree for schema `S`.
+    fn typed_tree<S: Schema>(&self, open: &OpenTree) -> Result<SledTree<S>> {
+        if open.schema != registry::schema_id::<S>() {
+            return Err(Error::SchemaCollision {
+                tree: S::TREE_NAME.0,
+                registered: open.schema.to_string(),
+                requested: registry::schema_id::<S>(),
+            });
+        }
+        Ok(SledTree::from_parts(
+            open.tree.clone(),
+            self.key_provider.clone(),
+            open.flusher.clone(),
+            open.indexes.clone(),
+        ))
+    }
+
+    /// Rewrites every value of schema `S` stored by an older version in the
+    /// current layout, returning the number of rewritten values.
+    ///
+    /// This walks the whole tree, so long-running services usually call it
+    /// from a background thread.
+    pub fn upgrade_all<S: Schema>(&self) -> Result<usize>
+    where
+        S::Value: UpgradableValue<S>,
+    {
+        self.get_tree::<S>()?.upgrade_all()
+    }
+
+    /// Rewrites the signed integer keys of schema `S` from the legacy layout
+    /// into the current one, returning the number of rewritten entries.
+    ///
+    /// Older releases encoded signed integer keys with plain `to_be_bytes`, so
+    /// negative keys sorted after positive ones. The migration is recorded in
+    /// a reserved metadata tree atomically with the rewrite, and later calls
+    /// do nothing, so it is safe to call at every startup. Call it before
+    /// writing to the tree with this release: the layouts cannot be told
+    /// apart, so keys written since would be scrambled.
+    ///
+    /// The tree is scanned before the rewrite transaction starts, as sled
+    /// transactions cannot iterate. Nothing may write to the tree while the
+    /// migration runs: an entry inserted between the scan and the commit
+    /// keeps its key unchanged.
+    ///
+    /// The entries of indexes attached to the tree move along with the keys,
+    /// so a tree with indexes attached fails with [`Error::IndexNotLoaded`]
+    /// until they are attached in this process too.
+    pub fn migrate_legacy_signed_keys<S: Schema>(&self) -> Result<usize>
+    where
+        S::Key: LegacySignedKey<S>,
+    {
+        let markers = self.inner_db.open_tree(LEGACY_KEYS_TREE)?;
+        self.get_tree::<S>()?.migrate_legacy_signed_keys(&markers)
+    }
+
+    /// Checks the checksum of every value in the tree of schema `S`.
+    ///
+    /// Returns the encoded keys of the entries whose checksum does not match.
+    #[cfg(feature = "checksum")]
+    pub fn verify_checksums<S: Schema>(&self) -> Result<Vec<Vec<u8>>>
+    where
+        S::Value: ChecksummedValue,
+    {
+        self.get_tree::<S>()?.verify_checksums()
+    }
+}
+
+/// Eagerly opens the tree of one schema.
+type OpenSchemaFn = fn(&SledDb) -> Result<()>;
+
+/// Builder for a [`SledDb`], created with [`SledDb::builder`].
+///
+/// Wraps [`sled::Config`] together with the typed-sled options:
+///
+/// ```no_run
+/// use typed_sled::{FlushPolicy, SledDb};
+/// # use typed_sled::{Schema, TreeName};
+/// # #[derive(Debug)]
+/// # struct UserSchema;
+/// # impl Schema for UserSchema {
+/// #     const TREE_NAME: TreeName = TreeName("users");
+/// #     type Key = u32;
+/// #     type Value = typed_sled::Rkyv<u64>;
+/// # }
+///
+/// let db = SledDb::builder()
+///     .path("mydb")
+///     .cache_capacity(256 * 1024 * 1024)
+///     .flush_policy(FlushPolicy::EveryN(100))
+///     .schema::<UserSchema>()
+///     .open()?;
+/// # Ok::<(), typed_sled::error::Error>(())
+/// ```
+pub struct SledDbBuilder {
+    config: Config,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    flush_policy: FlushPolicy,
+    schemas: Vec<OpenSchemaFn>,
+}
+
+impl std::fmt::Debug for SledDbBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SledDbBuilder")
+            .field("config", &self.config)
+            .field("key_provider", &self.key_provider)
+            .field("flush_policy", &self.flush_policy)
+            .field("schemas", &self.schemas.len())
+            .finish()
+    }
+}
+
+impl Default for SledDbBuilder {
+    fn default() -> Self {
+        Self {
+            config: Config::new(),
+            key_provider: None,
+            flush_policy: FlushPolicy::default(),
+            schemas: Vec::new(),
+        }
+    }
+}
+
+impl SledDbBuilder {
+    /// Sets the directory the database is stored in.
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.config = self.config.path(path);
+        self
+    }
+
+    /// Sets the maximum size in bytes of sled's page cache.
+    pub fn cache_capacity(mut self, bytes: u64) -> Self {
+        self.config = self.config.cache_capacity(bytes);
+        self
+    }
+
+    /// Enables zstd compression of sled's storage.
+    ///
+    /// Requires sled's `compression` feature, otherwise [`open`] fails.
+    ///
+    /// [`open`]: SledDbBuilder::open
+    pub fn use_compression(mut self, enabled: bool) -> Self {
+        self.config = self.config.use_compression(enabled);
+        self
+    }
+
+    /// Sets the zstd compression level, from 1 to 22.
+    pub fn compression_factor(mut self, factor: i32) -> Self {
+        self.config = self.config.compression_factor(factor);
+        self
+    }
+
+    /// Sets whether sled optimizes for space or for throughput.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.config = self.config.mode(mode);
+        self
+    }
+
+    /// Deletes the database when it is dropped. Without a path, it lives
+    /// in memory-backed storage.
+    pub fn temporary(mut self, temporary: bool) -> Self {
+        self.config = self.config.temporary(temporary);
+        self
+    }
+
+    /// Sets how often sled flushes in the background, or disables it.
+    pub fn flush_every_ms(mut self, every_ms: Option<u64>) -> Self {
+        self.config = self.config.flush_every_ms(every_ms);
+        self
+    }
+
+    /// Sets the flush policy of every tree, see [`SledDb::with_flush_policy`].
+    pub fn flush_policy(mut self, policy: FlushPolicy) -> Self {
+        self.flush_policy = policy;
+        self
+    }
+
+    /// Sets the key provider for encrypted values, see
+    /// [`SledDb::with_key_provider`].
+    pub fn key_provider(mut self, key_provider: impl KeyProvider + 'static) -> Self {
+        self.key_provider = Some(Arc::new(key_provider));
+        self
+    }
+
+    /// Opens the tree of schema `S` as part of [`open`], so that errors
+    /// surface at startup rather than on first use.
+    ///
+    /// [`open`]: SledDbBuilder::open
+    pub fn schema<S: Schema>(mut self) -> Self {
+        self.schemas.push(|db| db.get_tree::<S>().map(drop));
+        self
+    }
+
+    /// Opens the database and the trees of all declared schemas.
+    pub fn open(self) -> Result<SledDb> {
+        let db = SledDb {
+            inner_db: self.config.open()?,
+            inner_trees: DashMap::new(),
+            key_provider: self.key_provider,
+            flush_policy: self.flush_policy,
+        };
+        for open_schema in self.schemas {
+            open_schema(&db)?;
+        }
+        Ok(db)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{sync::Arc, thread};
+    use std::{any::type_name, sync::Arc, thread};
 
     use super::*;
     use crate::test_utils::*;
@@ -227,4 +616,178 @@ mod tests {
         // Cache should still have only one entry
         assert_eq!(db.inner_trees.len(), 1);
     }
+
+    #[test]
+    fn test_builder_opens_declared_schemas() {
+        let db = SledDb::builder()
+            .temporary(true)
+            .cache_capacity(1024 * 1024)
+            .mode(Mode::HighThroughput)
+            .flush_every_ms(None)
+            .flush_policy(FlushPolicy::Never)
+            .schema::<TestSchema1>()
+            .schema::<TestSchema2>()
+            .open()
+            .unwrap();
+
+        assert_eq!(db.inner_trees.len(), 2);
+        assert!(db.inner_trees.contains_key(&TestSchema1::TREE_NAME));
+        assert!(db.inner_trees.contains_key(&TestSchema2::TREE_NAME));
+
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        assert_eq!(tree.flush_policy(), FlushPolicy::Never);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        assert_test_values_eq(&TestValue::alice(), &tree.get(&1).unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_builder_reopens_path() {
+        let path = std::env::temp_dir().join(format!(
+            "typed-sled-builder-{}-{:?}",
+            std::process::id(),
+            thread::current().id()
+        ));
+
+        {
+            let db = SledDb::builder().path(&path).open().unwrap();
+            let tree = db.get_tree::<TestSchema1>().unwrap();
+            tree.insert(&1, &TestValue::alice()).unwrap();
+        }
+
+        // sled's background threads can hold the file lock briefly after
+        // the first handle is dropped. A failed temporary open would delete
+        // the database, so the path is removed by hand instead.
+        let mut attempts = 0;
+        let db = loop {
+            match SledDb::builder().path(&path).open() {
+                Ok(db) => break db,
+                Err(_) if attempts < 100 => {
+                    attempts += 1;
+                    thread::sleep(std::time::Duration::from_millis(10));
+                }
+                Err(err) => panic!("failed to reopen {}: {err}", path.display()),
+            }
+        };
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        let value = tree.get(&1).unwrap();
+        drop((tree, db));
+        let _ = std::fs::remove_dir_all(&path);
+        assert_test_values_eq(&TestValue::alice(), &value.unwrap());
+    }
+
+    #[test]
+    fn test_builder_rejects_invalid_config() {
+        let result = SledDb::builder()
+            .temporary(true)
+            .compression_factor(30)
+            .open();
+
+        assert!(matches!(result, Err(crate::error::Error::SledError(_))));
+    }
+
+    /// Declares the same tree as [`TestSchema1`] with a different schema type.
+    #[derive(Debug)]
+    struct ImpostorSchema;
+
+    impl Schema for ImpostorSchema {
+        const TREE_NAME: TreeName = TestSchema1::TREE_NAME;
+        type Key = u32;
+        type Value = TestValue;
+    }
+
+    mod v1 {
+        use super::*;
+
+        #[derive(Debug)]
+        pub(super) struct AccountSchema;
+
+        impl Schema for AccountSchema {
+            const TREE_NAME: TreeName = TreeName("accounts");
+            type Key = u32;
+            type Value = TestValue;
+        }
+    }
+
+    mod v2 {
+        use super::*;
+
+        #[derive(Debug)]
+        pub(super) struct AccountSchema;
+
+        impl Schema for AccountSchema {
+            const TREE_NAME: TreeName = TreeName("accounts");
+            type Key = u32;
+            type Value = TestValue;
+            const VERSION: u32 = 2;
+        }
+    }
+
+    #[derive(Debug)]
+    struct ReservedSchema;
+
+    impl Schema for ReservedSchema {
+        const TREE_NAME: TreeName = TreeName("__typed_sled_schemas");
+        type Key = u32;
+        type Value = TestValue;
+    }
+
+    #[test]
+    fn test_tree_name_collision_is_rejected() {
+        let sled_db = create_temp_sled_db();
+        let db = SledDb::new(sled_db.clone()).unwrap();
+        db.get_tree::<TestSchema1>().unwrap();
+
+        let err = db.get_tree::<ImpostorSchema>().unwrap_err();
+        assert!(matches!(err, Error::SchemaCollision { tree: "test1", .. }));
+
+        // The registration outlives the `SledDb` that made it.
+        let reopened = SledDb::new(sled_db).unwrap();
+        let err = reopened.get_tree::<ImpostorSchema>().unwrap_err();
+        match err {
+            Error::SchemaCollision {
+                registered,
+                requested,
+                ..
+            } => {
+                assert_eq!(registered, type_name::<TestSchema1>());
+                assert_eq!(requested, type_name::<ImpostorSchema>());
+            }
+            other => panic!("expected schema collision, got {other:?}"),
+        }
+        assert!(reopened.get_tree::<TestSchema1>().is_ok());
+    }
+
+    #[test]
+    fn test_schema_version_mismatch_and_reregistration() {
+        let sled_db = create_temp_sled_db();
+        SledDb::new(sled_db.clone())
+            .unwrap()
+            .get_tree::<v1::AccountSchema>()
+            .unwrap()
+            .insert(&1, &TestValue::alice())
+            .unwrap();
+
+        // A new release renames the schema type and bumps its version.
+        let db = SledDb::new(sled_db).unwrap();
+        assert!(matches!(
+            db.get_tree::<v2::AccountSchema>().unwrap_err(),
+            Error::SchemaCollision { .. }
+        ));
+
+        db.register_schema::<v2::AccountSchema>().unwrap();
+        let tree = db.get_tree::<v2::AccountSchema>().unwrap();
+        assert_test_values_eq(&TestValue::alice(), &tree.get(&1).unwrap().unwrap());
+        assert!(db.get_tree::<v1::AccountSchema>().is_err());
+    }
+
+    #[test]
+    fn test_reserved_tree_names_are_rejected() {
+        let db = create_test_db().unwrap();
+
+        assert!(matches!(
+            db.get_tree::<ReservedSchema>().unwrap_err(),
+            Error::ReservedTreeName("__typed_sled_schemas")
+        ));
+        assert!(db.register_schema::<ReservedSchema>().is_err());
+    }
 }
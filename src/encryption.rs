@@ -0,0 +1,471 @@
+use std::collections::BTreeMap;
+#[cfg(feature = "encryption")]
+use std::ops::Deref;
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    XChaCha20Poly1305, XNonce,
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+};
+#[cfg(feature = "encryption")]
+use sled::IVec;
+
+#[cfg(feature = "encryption")]
+use crate::{CodecError, CodecResult, Schema, ValueCodec, codec::CodecContext};
+
+/// A 256-bit symmetric encryption key.
+pub type EncryptionKey = [u8; 32];
+
+/// Source of the keys used to encrypt and decrypt values at rest.
+///
+/// Every encrypted value records the id of the key it was written with, so
+/// keys can be rotated by changing [`KeyProvider::current_key_id`] while
+/// still returning retired keys from [`KeyProvider::key`].
+pub trait KeyProvider: std::fmt::Debug + Send + Sync {
+    /// Returns the id of the key used to encrypt new values.
+    fn current_key_id(&self) -> u32;
+
+    /// Returns the key with the given id, or `None` if it is unknown.
+    fn key(&self, key_id: u32) -> Option<EncryptionKey>;
+}
+
+/// A [`KeyProvider`] over a fixed set of in-memory keys.
+#[derive(Clone)]
+pub struct StaticKeyProvider {
+    current_key_id: u32,
+    keys: BTreeMap<u32, EncryptionKey>,
+}
+
+impl StaticKeyProvider {
+    /// Creates a provider that encrypts new values with `key`.
+    pub fn new(key_id: u32, key: EncryptionKey) -> Self {
+        Self {
+            current_key_id: key_id,
+            keys: BTreeMap::from([(key_id, key)]),
+        }
+    }
+
+    /// Adds a retired key that is still used to decrypt existing values.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `key_id` is already registered with a different key.
+    pub fn with_key(mut self, key_id: u32, key: EncryptionKey) -> Self {
+        let existing = self.keys.entry(key_id).or_insert(key);
+        assert!(
+            *existing == key,
+            "key id {key_id} is already registered with a different key"
+        );
+        self
+    }
+}
+
+impl std::fmt::Debug for StaticKeyProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticKeyProvider")
+            .field("current_key_id", &self.current_key_id)
+            .field("key_ids", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn current_key_id(&self) -> u32 {
+        self.current_key_id
+    }
+
+    fn key(&self, key_id: u32) -> Option<EncryptionKey> {
+        self.keys.get(&key_id).copied()
+    }
+}
+
+/// Length of the key id written in front of every encrypted value.
+#[cfg(feature = "encryption")]
+const KEY_ID_LEN: usize = 4;
+/// Length of the random XChaCha20-Poly1305 nonce following the key id.
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+/// Value codec combinator that encrypts the output of codec `C` with
+/// XChaCha20-Poly1305.
+///
+/// Keys come from the [`KeyProvider`] the [`SledDb`](crate::SledDb) was
+/// opened with. Each value is stored as a 4-byte big-endian key id, a
+/// random 24-byte nonce and the ciphertext. The tree name is bound in as
+/// associated data, so a value copied into another tree fails to decrypt.
+///
+/// Encrypting without a key provider, for example through a [`SledBatch`]
+/// created with [`SledBatch::new`], fails with
+/// [`CodecError::MissingKeyProvider`]; use [`SledTree::batch`] instead.
+///
+/// [`SledBatch`]: crate::batch::SledBatch
+/// [`SledBatch::new`]: crate::batch::SledBatch::new
+/// [`SledTree::batch`]: crate::SledTree::batch
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Encrypted<C>(pub C);
+
+#[cfg(feature = "encryption")]
+impl<C> Encrypted<C> {
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<C> From<C> for Encrypted<C> {
+    fn from(value: C) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl<C> Deref for Encrypted<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Looks up key `key_id`, reporting failures against schema `S`.
+#[cfg(feature = "encryption")]
+fn cipher<S: Schema>(ctx: &CodecContext<'_>, key_id: u32) -> CodecResult<XChaCha20Poly1305> {
+    let provider = ctx.key_provider().ok_or(CodecError::MissingKeyProvider {
+        schema: S::TREE_NAME.0,
+    })?;
+    let key = provider
+        .key(key_id)
+        .ok_or(CodecError::UnknownEncryptionKey {
+            schema: S::TREE_NAME.0,
+            key_id,
+        })?;
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Decrypts a value written by [`Encrypted`], returning the plaintext.
+#[cfg(feature = "encryption")]
+fn decrypt<S: Schema>(buf: &[u8], ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+    if buf.len() < KEY_ID_LEN + NONCE_LEN {
+        return Err(CodecError::DecryptionFailed {
+            schema: S::TREE_NAME.0,
+        });
+    }
+    let (key_id, rest) = buf.split_at(KEY_ID_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+    let key_id = u32::from_be_bytes(key_id.try_into().expect("split at KEY_ID_LEN"));
+
+    let payload = Payload {
+        msg: ciphertext,
+        aad: S::TREE_NAME.0.as_bytes(),
+    };
+    cipher::<S>(ctx, key_id)?
+        .decrypt(XNonce::from_slice(nonce), payload)
+        .map_err(|_| CodecError::DecryptionFailed {
+            schema: S::TREE_NAME.0,
+        })
+}
+
+#[cfg(feature = "encryption")]
+impl<S, C> ValueCodec<S> for Encrypted<C>
+where
+    S: Schema,
+    C: ValueCodec<S>,
+{
+    type Decoded = C::Decoded;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        self.encode_value_with(&CodecContext::default())
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        Self::decode_value_with(buf, &CodecContext::default())
+    }
+
+    fn encode_value_with(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        let plaintext = self.0.encode_value_with(ctx)?;
+        let key_id = ctx
+            .key_provider()
+            .ok_or(CodecError::MissingKeyProvider {
+                schema: S::TREE_NAME.0,
+            })?
+            .current_key_id();
+        let cipher = cipher::<S>(ctx, key_id)?;
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let payload = Payload {
+            msg: &plaintext,
+            aad: S::TREE_NAME.0.as_bytes(),
+        };
+        let ciphertext =
+            cipher
+                .encrypt(&nonce, payload)
+                .map_err(|e| CodecError::SerializationFailed {
+                    schema: S::TREE_NAME.0,
+                    source: e.into(),
+                })?;
+
+        let mut buf = Vec::with_capacity(KEY_ID_LEN + NONCE_LEN + ciphertext.len());
+        buf.extend_from_slice(&key_id.to_be_bytes());
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&ciphertext);
+        Ok(buf)
+    }
+
+    fn decode_value_with(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self::Decoded> {
+        C::decode_value_with(decrypt::<S>(&buf, ctx)?.into(), ctx)
+    }
+
+    /// Every encoding uses a fresh nonce, so values are compared by their
+    /// plaintext instead.
+    const STABLE_ENCODING: bool = false;
+
+    fn stable_form(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<IVec> {
+        C::stable_form(decrypt::<S>(&buf, ctx)?.into(), ctx)
+    }
+
+    fn encode_stable_form(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        self.0.encode_stable_form(ctx)
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+    use crate::{
+        KeyCodec, Rkyv, SledDb, SledTree, TreeName, batch::SledBatch, error::Error, test_utils::*,
+        transaction::SledTransactional,
+    };
+
+    #[derive(Debug)]
+    struct SecretsSchema;
+
+    impl Schema for SecretsSchema {
+        const TREE_NAME: TreeName = TreeName("secrets");
+        type Key = u32;
+        type Value = Encrypted<Rkyv<TestValue>>;
+    }
+
+    #[derive(Debug)]
+    struct OtherSecretsSchema;
+
+    impl Schema for OtherSecretsSchema {
+        const TREE_NAME: TreeName = TreeName("other_secrets");
+        type Key = u32;
+        type Value = Encrypted<Rkyv<TestValue>>;
+    }
+
+    const KEY_1: EncryptionKey = [1; 32];
+    const KEY_2: EncryptionKey = [2; 32];
+
+    fn secret(value: TestValue) -> Encrypted<Rkyv<TestValue>> {
+        Encrypted(Rkyv::new(value))
+    }
+
+    fn raw_value<S: Schema<Key = u32>>(tree: &SledTree<S>, key: u32) -> IVec {
+        let key = <u32 as KeyCodec<S>>::encode_key(&key).unwrap();
+        tree.inner.get(key).unwrap().unwrap()
+    }
+
+    fn expect_codec_error<T: std::fmt::Debug>(result: crate::error::Result<T>) -> CodecError {
+        match result {
+            Err(Error::CodecError(err)) => err,
+            other => panic!("expected codec error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip() {
+        let sled_db = create_temp_sled_db();
+        let db = SledDb::with_key_provider(sled_db, StaticKeyProvider::new(1, KEY_1)).unwrap();
+        let tree = db.get_tree::<SecretsSchema>().unwrap();
+
+        tree.insert(&1, &secret(TestValue::alice())).unwrap();
+
+        let raw = raw_value(&tree, 1);
+        assert_eq!(raw[..KEY_ID_LEN], 1u32.to_be_bytes());
+        assert!(!raw.windows(5).any(|w| w == b"Alice"));
+        assert_test_values_eq(&TestValue::alice(), &tree.get(&1).unwrap().unwrap());
+
+        // Equal plaintexts get different nonces.
+        tree.insert(&2, &secret(TestValue::alice())).unwrap();
+        assert_ne!(raw_value(&tree, 1), raw_value(&tree, 2));
+    }
+
+    #[test]
+    fn test_encrypted_iterators_batches_and_transactions() {
+        let sled_db = create_temp_sled_db();
+        let db = SledDb::with_key_provider(sled_db, StaticKeyProvider::new(1, KEY_1)).unwrap();
+        let tree = db.get_tree::<SecretsSchema>().unwrap();
+
+        let mut batch = tree.batch();
+        batch.insert(1, secret(TestValue::alice())).unwrap();
+        tree.apply_batch(batch).unwrap();
+
+        (&tree,)
+            .transaction(|(t,)| {
+                assert!(t.get(&1)?.is_some());
+                t.insert(&2, &secret(TestValue::bob()))?;
+                Ok::<_, sled::transaction::ConflictableTransactionError<Error>>(())
+            })
+            .unwrap();
+
+        let values: Vec<_> = tree.iter().map(|item| item.unwrap().1).collect();
+        assert_eq!(values, vec![TestValue::alice(), TestValue::bob()]);
+        let (_, last) = tree.last().unwrap().unwrap();
+        assert_test_values_eq(&TestValue::bob(), &last);
+
+        let mut detached = SledBatch::<SecretsSchema>::new();
+        let err = detached
+            .insert(3, secret(TestValue::charlie()))
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::CodecError(CodecError::MissingKeyProvider { .. })
+        ));
+    }
+
+    #[test]
+    fn test_compare_and_swap_compares_plaintext() {
+        let sled_db = create_temp_sled_db();
+        let old = SledDb::with_key_provider(sled_db.clone(), StaticKeyProvider::new(1, KEY_1))
+            .unwrap()
+            .get_tree::<SecretsSchema>()
+            .unwrap();
+        old.insert(&1, &secret(TestValue::alice())).unwrap();
+
+        // The stored value was encrypted under a retired key and another nonce.
+        let provider = StaticKeyProvider::new(2, KEY_2).with_key(1, KEY_1);
+        let tree = SledDb::with_key_provider(sled_db, provider)
+            .unwrap()
+            .get_tree::<SecretsSchema>()
+            .unwrap();
+        tree.compare_and_swap(
+            1,
+            Some(secret(TestValue::alice())),
+            Some(secret(TestValue::bob())),
+        )
+        .unwrap()
+        .unwrap();
+        assert_test_values_eq(&TestValue::bob(), &tree.get(&1).unwrap().unwrap());
+
+        let err = tree
+            .compare_and_swap(1, Some(secret(TestValue::alice())), None)
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.current, Some(TestValue::bob()));
+        let err = tree
+            .compare_and_swap(1, None, Some(secret(TestValue::charlie())))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(err.current, Some(TestValue::bob()));
+
+        tree.compare_and_swap(2, None, Some(secret(TestValue::charlie())))
+            .unwrap()
+            .unwrap();
+        tree.compare_and_swap(1, Some(secret(TestValue::bob())), None)
+            .unwrap()
+            .unwrap();
+        assert!(tree.get(&1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_key_rotation() {
+        let sled_db = create_temp_sled_db();
+        let old = SledDb::with_key_provider(sled_db.clone(), StaticKeyProvider::new(1, KEY_1))
+            .unwrap()
+            .get_tree::<SecretsSchema>()
+            .unwrap();
+        old.insert(&1, &secret(TestValue::alice())).unwrap();
+
+        let provider = StaticKeyProvider::new(2, KEY_2).with_key(1, KEY_1);
+        let tree = SledDb::with_key_provider(sled_db.clone(), provider)
+            .unwrap()
+            .get_tree::<SecretsSchema>()
+            .unwrap();
+        tree.insert(&2, &secret(TestValue::bob())).unwrap();
+
+        assert_eq!(raw_value(&tree, 1)[..KEY_ID_LEN], 1u32.to_be_bytes());
+        assert_eq!(raw_value(&tree, 2)[..KEY_ID_LEN], 2u32.to_be_bytes());
+        assert_test_values_eq(&TestValue::alice(), &tree.get(&1).unwrap().unwrap());
+        assert_test_values_eq(&TestValue::bob(), &tree.get(&2).unwrap().unwrap());
+
+        // A provider that dropped the old key can no longer read old values.
+        let new_only = SledDb::with_key_provider(sled_db, StaticKeyProvider::new(2, KEY_2))
+            .unwrap()
+            .get_tree::<SecretsSchema>()
+            .unwrap();
+        assert!(matches!(
+            expect_codec_error(new_only.get(&1)),
+            CodecError::UnknownEncryptionKey { key_id: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn test_reregistering_the_same_key_is_allowed() {
+        let provider = StaticKeyProvider::new(2, KEY_2)
+            .with_key(1, KEY_1)
+            .with_key(1, KEY_1)
+            .with_key(2, KEY_2);
+        assert_eq!(provider.key(1), Some(KEY_1));
+        assert_eq!(provider.key(2), Some(KEY_2));
+    }
+
+    #[test]
+    #[should_panic(expected = "key id 1 is already registered with a different key")]
+    fn test_conflicting_key_id_panics() {
+        let _ = StaticKeyProvider::new(1, KEY_1).with_key(1, KEY_2);
+    }
+
+    #[test]
+    fn test_values_cannot_move_between_trees() {
+        let sled_db = create_temp_sled_db();
+        let db = SledDb::with_key_provider(sled_db, StaticKeyProvider::new(1, KEY_1)).unwrap();
+        let secrets = db.get_tree::<SecretsSchema>().unwrap();
+        let other = db.get_tree::<OtherSecretsSchema>().unwrap();
+
+        secrets.insert(&1, &secret(TestValue::alice())).unwrap();
+        let key = <u32 as KeyCodec<OtherSecretsSchema>>::encode_key(&1).unwrap();
+        other.inner.insert(key, raw_value(&secrets, 1)).unwrap();
+
+        assert!(matches!(
+            expect_codec_error(other.get(&1)),
+            CodecError::DecryptionFailed {
+                schema: "other_secrets"
+            }
+        ));
+    }
+
+    #[test]
+    fn test_tampered_and_truncated_values_are_rejected() {
+        let sled_db = create_temp_sled_db();
+        let db = SledDb::with_key_provider(sled_db, StaticKeyProvider::new(1, KEY_1)).unwrap();
+        let tree = db.get_tree::<SecretsSchema>().unwrap();
+        tree.insert(&1, &secret(TestValue::alice())).unwrap();
+
+        let key = <u32 as KeyCodec<SecretsSchema>>::encode_key(&1).unwrap();
+        let mut raw = raw_value(&tree, 1).to_vec();
+        *raw.last_mut().unwrap() ^= 1;
+        tree.inner.insert(&key, raw).unwrap();
+        assert!(matches!(
+            expect_codec_error(tree.get(&1)),
+            CodecError::DecryptionFailed { .. }
+        ));
+
+        tree.inner.insert(&key, vec![0_u8; 10]).unwrap();
+        assert!(matches!(
+            expect_codec_error(tree.get(&1)),
+            CodecError::DecryptionFailed { .. }
+        ));
+    }
+
+    #[test]
+    fn test_missing_key_provider() {
+        let tree = create_temp_tree::<SecretsSchema>().unwrap();
+
+        assert!(matches!(
+            expect_codec_error(tree.insert(&1, &secret(TestValue::alice()))),
+            CodecError::MissingKeyProvider { schema: "secrets" }
+        ));
+    }
+}
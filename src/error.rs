@@ -1,6 +1,9 @@
 // re-export `ConflictableTransactionError`
 pub use sled::transaction::ConflictableTransactionError;
-use sled::{CompareAndSwapError, Error as SledError, transaction::UnabortableTransactionError};
+use sled::{
+    CompareAndSwapError, Error as SledError,
+    transaction::{TransactionError, UnabortableTransactionError},
+};
 
 use crate::CodecError;
 
@@ -26,6 +29,73 @@ pub enum Error {
     /// Custom abort error for transactions
     #[error("abort: {0}")]
     Abort(Box<dyn std::error::Error + Send + Sync + 'static>),
+
+    /// Tree is registered to a different schema type
+    #[error("tree '{tree}' is registered to schema '{registered}', not '{requested}'")]
+    SchemaCollision {
+        /// The tree name both schemas declare.
+        tree: &'static str,
+        /// The schema type the tree is registered to.
+        registered: String,
+        /// The schema type that tried to open the tree.
+        requested: &'static str,
+    },
+
+    /// Schema does not match the registration of its tree
+    #[error("schema of tree '{tree}' changed its {field} from '{stored}' to '{declared}'")]
+    SchemaMismatch {
+        /// The tree name of the schema.
+        tree: &'static str,
+        /// The part of the schema that changed.
+        field: &'static str,
+        /// The registered value.
+        stored: String,
+        /// The value declared by the schema.
+        declared: String,
+    },
+
+    /// Tree name is reserved for typed-sled's own metadata
+    #[error("tree name '{0}' is reserved")]
+    ReservedTreeName(&'static str),
+
+    /// Index is not attached to the indexed tree
+    #[error("index '{index}' is not attached to tree '{schema}'")]
+    IndexNotFound {
+        /// The tree name of the indexed schema.
+        schema: &'static str,
+        /// The tree name of the index.
+        index: &'static str,
+    },
+
+    /// Write would give a second entry the same key in a unique index
+    #[error("duplicate key {key:?} in unique index '{index}' of tree '{schema}'")]
+    UniqueViolation {
+        /// The tree name of the indexed schema.
+        schema: &'static str,
+        /// The tree name of the unique index.
+        index: &'static str,
+        /// The encoded index key.
+        key: Vec<u8>,
+    },
+
+    /// Write to a tree with an index attached that this process has not loaded
+    #[error(
+        "tree '{schema}' has index '{index}' attached, load it with IndexedTree::with_index before writing"
+    )]
+    IndexNotLoaded {
+        /// The tree name of the indexed schema.
+        schema: &'static str,
+        /// The tree name of the index.
+        index: String,
+    },
+
+    /// Migration steps do not fit the database's applied migrations
+    #[error("invalid migrations: {0}")]
+    InvalidMigrations(String),
+
+    /// Page cursor is not valid for the schema it is parsed for
+    #[error("invalid cursor: {0}")]
+    InvalidCursor(String),
 }
 
 impl From<Error> for ConflictableTransactionError<Error> {
@@ -35,6 +105,15 @@ impl From<Error> for ConflictableTransactionError<Error> {
 }
 
 impl Error {
+    /// Unwraps the error of a transaction run by typed-sled itself, whose
+    /// closure aborts with an [`Error`].
+    pub(crate) fn from_transaction(err: TransactionError<Error>) -> Self {
+        match err {
+            TransactionError::Abort(err) => err,
+            TransactionError::Storage(err) => err.into(),
+        }
+    }
+
     /// Creates an abort error from any error type.
     ///
     /// This is useful for aborting transactions with custom application errors.
@@ -0,0 +1,354 @@
+use std::{
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread,
+};
+
+use sled::{Tree, transaction::TransactionalTree};
+
+/// When writes are flushed to disk.
+///
+/// Set for a whole database with [`SledDb::with_flush_policy`] or for a
+/// single tree handle with [`SledTree::with_flush_policy`]. Every write
+/// path, including batches and transactions, follows the same policy.
+///
+/// [`SledDb::with_flush_policy`]: crate::SledDb::with_flush_policy
+/// [`SledTree::with_flush_policy`]: crate::SledTree::with_flush_policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FlushPolicy {
+    /// Flush synchronously after every write.
+    #[default]
+    Always,
+    /// Never flush explicitly and rely on sled's `flush_every_ms`.
+    Never,
+    /// Flush synchronously after every `n`th write. `EveryN(0)` and
+    /// `EveryN(1)` behave like [`FlushPolicy::Always`].
+    EveryN(u64),
+    /// Start a flush on sled's background thread pool after every write
+    /// without waiting for it to complete. Writes made while the tree's
+    /// previous flush is still running are flushed once it completes.
+    ///
+    /// Writes never fail because of these flushes. A failed flush is
+    /// reported by the next [`SledTree::flush`] instead.
+    ///
+    /// [`SledTree::flush`]: crate::SledTree::flush
+    Async,
+}
+
+/// A flush running on sled's thread pool.
+type PendingFlush = Pin<Box<dyn Future<Output = sled::Result<usize>> + Send>>;
+
+/// Applies a [`FlushPolicy`] to the writes of one tree.
+#[derive(Default)]
+pub(crate) struct Flusher {
+    policy: FlushPolicy,
+    /// Writes seen so far, for [`FlushPolicy::EveryN`].
+    writes: AtomicU64,
+    /// State of the flushes of [`FlushPolicy::Async`].
+    background: Arc<BackgroundFlush>,
+}
+
+/// The flushes of a tree running on sled's thread pool.
+#[derive(Default)]
+struct BackgroundFlush {
+    /// The running flush, if any.
+    pending: Mutex<Option<PendingFlush>>,
+    /// Whether writes arrived that no started flush covers.
+    dirty: AtomicBool,
+    /// The error of the last failed flush not yet reported.
+    failure: Mutex<Option<sled::Error>>,
+}
+
+/// Wakes when the running flush of a tree completes.
+struct FlushWaker {
+    background: Arc<BackgroundFlush>,
+    tree: Tree,
+}
+
+impl Wake for FlushWaker {
+    fn wake(self: Arc<Self>) {
+        // sled wakes while completing the flush, before its result can be
+        // polled, so the next flush is started from another thread.
+        if self.background.dirty.load(Ordering::SeqCst) {
+            thread::spawn(move || self.background.drive(&self.tree));
+        }
+    }
+}
+
+impl BackgroundFlush {
+    /// Polls the running flush of `tree`, starting another one once it
+    /// completes if writes arrived since it started.
+    ///
+    /// Flushes queued faster than the pool runs them can exhaust it and
+    /// stall sled, which flushes on the same pool, so at most one runs at a
+    /// time. Failures are recorded rather than returned, as they may belong
+    /// to earlier writes.
+    fn drive(self: &Arc<Self>, tree: &Tree) {
+        let waker = Waker::from(Arc::new(FlushWaker {
+            background: self.clone(),
+            tree: tree.clone(),
+        }));
+        let mut cx = Context::from_waker(&waker);
+        let mut pending = self.pending.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(flush) = pending.as_mut() {
+                match flush.as_mut().poll(&mut cx) {
+                    Poll::Pending => return,
+                    Poll::Ready(result) => {
+                        *pending = None;
+                        self.record(result);
+                    }
+                }
+            }
+            if !self.dirty.swap(false, Ordering::SeqCst) {
+                return;
+            }
+            // The first poll hands the flush to sled's thread pool.
+            let tree = tree.clone();
+            *pending = Some(Box::pin(async move { tree.flush_async().await }));
+        }
+    }
+
+    /// Keeps the error of a failed flush until it is reported.
+    fn record(&self, result: sled::Result<usize>) {
+        if let Err(err) = result {
+            *self.failure.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+        }
+    }
+}
+
+impl fmt::Debug for Flusher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Flusher")
+            .field("policy", &self.policy)
+            .field("writes", &self.writes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Flusher {
+    pub(crate) fn new(policy: FlushPolicy) -> Self {
+        Self {
+            policy,
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn policy(&self) -> FlushPolicy {
+        self.policy
+    }
+
+    /// Records a write and returns whether it must be flushed synchronously.
+    fn should_flush(&self) -> bool {
+        match self.policy {
+            FlushPolicy::Always => true,
+            FlushPolicy::Never | FlushPolicy::Async => false,
+            FlushPolicy::EveryN(n) => {
+                let writes = self.writes.fetch_add(1, Ordering::Relaxed) + 1;
+                writes.is_multiple_of(n.max(1))
+            }
+        }
+    }
+
+    /// Flushes `tree` after a write as the policy requires.
+    pub(crate) fn after_write(&self, tree: &Tree) -> sled::Result<()> {
+        if self.policy == FlushPolicy::Async {
+            self.spawn_flush(tree);
+            return Ok(());
+        }
+        if self.should_flush() {
+            tree.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Marks a transaction to flush on commit after a write as the policy
+    /// requires. Asynchronous flushes start after the commit instead, see
+    /// [`Flusher::after_transaction`].
+    pub(crate) fn after_transactional_write(&self, tree: &TransactionalTree) {
+        if self.should_flush() {
+            tree.flush();
+        }
+    }
+
+    /// Starts an asynchronous flush of `tree` after a committed transaction.
+    ///
+    /// The transaction has committed, so a failed flush must not fail it.
+    pub(crate) fn after_transaction(&self, tree: &Tree) {
+        if self.policy == FlushPolicy::Async {
+            self.spawn_flush(tree);
+        }
+    }
+
+    /// Returns the error of a failed asynchronous flush not yet reported.
+    pub(crate) fn take_failure(&self) -> sled::Result<()> {
+        let mut failure = self
+            .background
+            .failure
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        failure.take().map_or(Ok(()), Err)
+    }
+
+    /// Flushes `tree` on sled's thread pool without waiting for it, once
+    /// the running flush completes if there is one.
+    fn spawn_flush(&self, tree: &Tree) {
+        self.background.dirty.store(true, Ordering::SeqCst);
+        self.background.drive(tree);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SledDb, batch::SledBatch, test_utils::*, transaction::SledTransactional};
+
+    fn flushes(policy: FlushPolicy, writes: usize) -> Vec<bool> {
+        let flusher = Flusher::new(policy);
+        (0..writes).map(|_| flusher.should_flush()).collect()
+    }
+
+    #[test]
+    fn test_policy_flush_schedule() {
+        assert_eq!(flushes(FlushPolicy::Always, 3), vec![true; 3]);
+        assert_eq!(flushes(FlushPolicy::Never, 3), vec![false; 3]);
+        assert_eq!(flushes(FlushPolicy::Async, 3), vec![false; 3]);
+        assert_eq!(
+            flushes(FlushPolicy::EveryN(3), 6),
+            vec![false, false, true, false, false, true]
+        );
+        assert_eq!(flushes(FlushPolicy::EveryN(0), 2), vec![true; 2]);
+    }
+
+    #[test]
+    fn test_db_policy_applies_to_trees() {
+        let db = SledDb::new(create_temp_sled_db())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::EveryN(2));
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        assert_eq!(tree.flush_policy(), FlushPolicy::EveryN(2));
+
+        // Handles of the same tree share one write counter.
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        let again = db.get_tree::<TestSchema1>().unwrap();
+        assert!(again.flusher.should_flush());
+
+        let tree = tree.with_flush_policy(FlushPolicy::Never);
+        assert_eq!(tree.flush_policy(), FlushPolicy::Never);
+        assert_eq!(
+            db.get_tree::<TestSchema1>().unwrap().flush_policy(),
+            FlushPolicy::EveryN(2)
+        );
+    }
+
+    #[test]
+    fn test_every_write_path_follows_policy() {
+        for policy in [
+            FlushPolicy::Always,
+            FlushPolicy::Never,
+            FlushPolicy::EveryN(2),
+            FlushPolicy::Async,
+        ] {
+            let db = SledDb::new(create_temp_sled_db())
+                .unwrap()
+                .with_flush_policy(policy);
+            let tree = db.get_tree::<TestSchema1>().unwrap();
+
+            tree.insert(&1, &TestValue::alice()).unwrap();
+            tree.compare_and_swap(2, None, Some(TestValue::bob()))
+                .unwrap()
+                .unwrap();
+            let mut batch = SledBatch::<TestSchema1>::new();
+            batch.insert(3, TestValue::charlie()).unwrap();
+            tree.apply_batch(batch).unwrap();
+            (&tree,)
+                .transaction(|(t,)| {
+                    t.insert(&4, &TestValue::new_with_name(4))?;
+                    Ok::<_, sled::transaction::ConflictableTransactionError<crate::error::Error>>(())
+                })
+                .unwrap();
+            assert!(tree.take(&1).unwrap().is_some());
+            tree.remove(&2).unwrap();
+            tree.update_and_fetch(&5, |_| Some(TestValue::new_with_name(5)))
+                .unwrap();
+            tree.fetch_and_update(&5, |_| None).unwrap();
+            tree.insert(&6, &TestValue::new_with_name(6)).unwrap();
+            tree.insert(&7, &TestValue::new_with_name(7)).unwrap();
+            assert_eq!(tree.pop_max().unwrap().unwrap().0, 7);
+            assert_eq!(tree.pop_min_range(5..).unwrap().unwrap().0, 6);
+
+            let keys: Vec<_> = tree.iter().map(|item| item.unwrap().0).collect();
+            assert_eq!(keys, vec![3, 4], "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn test_empty_pops_do_not_flush() {
+        let db = SledDb::new(create_temp_sled_db())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::EveryN(2));
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        assert!(tree.pop_min().unwrap().is_none());
+        assert!(tree.pop_max().unwrap().is_none());
+        assert!(tree.pop_min_range(1..).unwrap().is_none());
+
+        // None of the pops counted as a write.
+        assert!(!tree.flusher.should_flush());
+        assert!(tree.flusher.should_flush());
+    }
+
+    #[test]
+    fn test_async_flush_covers_writes_during_running_flush() {
+        let db = SledDb::new(create_temp_sled_db())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::Async);
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        for id in 0..100 {
+            tree.insert(&id, &TestValue::new_with_name(id)).unwrap();
+        }
+
+        // Writes made while a flush ran start another one when it completes,
+        // without waiting for a later write.
+        let background = &tree.flusher.background;
+        for _ in 0..500 {
+            if !background.dirty.load(Ordering::SeqCst) {
+                break;
+            }
+            thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!background.dirty.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_async_flush_failure_is_reported_by_next_flush() {
+        let db = SledDb::new(create_temp_sled_db())
+            .unwrap()
+            .with_flush_policy(FlushPolicy::Async);
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        tree.flusher
+            .background
+            .record(Err(sled::Error::Unsupported("flush failed".into())));
+
+        // Writes and committed transactions do not fail on an earlier flush.
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        (&tree,)
+            .transaction(|(t,)| {
+                t.insert(&2, &TestValue::bob())?;
+                Ok::<_, sled::transaction::ConflictableTransactionError<crate::error::Error>>(())
+            })
+            .unwrap();
+        assert_eq!(tree.iter().count(), 2);
+
+        assert!(matches!(
+            tree.flush(),
+            Err(crate::error::Error::SledError(sled::Error::Unsupported(_)))
+        ));
+        tree.flush().unwrap();
+    }
+}
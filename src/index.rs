@@ -0,0 +1,1241 @@
+use std::{
+    borrow::Borrow,
+    collections::BTreeMap,
+    fmt::Debug,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+
+use sled::{
+    Batch, Db, IVec, Iter, Tree,
+    transaction::{
+        ConflictableTransactionError, ConflictableTransactionResult, TransactionResult,
+        TransactionalTree, UnabortableTransactionError,
+    },
+};
+
+use crate::{
+    KeyCodec, KeyLookup, Schema, SledTree, TreeName, ValueCodec,
+    codec::CodecContext,
+    error::{Error, Result},
+    registry::{self, RESERVED_PREFIX},
+    transaction::SledTransactional,
+    tree::{DecodedValue, SledTransactionalTree, SledTreeIter},
+};
+
+/// A secondary index over the values of schema `S`.
+///
+/// The index lives in its own tree and maps the index key of every value to
+/// the primary keys of the entries holding it. Attach it to the tree with
+/// [`IndexedTree::with_index`], after which every write to the tree keeps
+/// it in sync.
+///
+/// A unique index maps each index key to a single entry. Writes that would
+/// give a second entry the same index key fail with
+/// [`Error::UniqueViolation`], checked in the transaction of the write.
+///
+/// ```ignore
+/// #[derive(Debug)]
+/// struct UsersByEmail;
+///
+/// impl Index<UserSchema> for UsersByEmail {
+///     const TREE_NAME: TreeName = TreeName("users_by_email");
+///     const UNIQUE: bool = true;
+///     type Key = String;
+///
+///     fn index_key(user: &User) -> Option<String> {
+///         Some(user.email.clone())
+///     }
+/// }
+/// ```
+pub trait Index<S: Schema>: Debug + Send + Sync + Sized + 'static {
+    /// The name of the tree storing the index.
+    const TREE_NAME: TreeName;
+
+    /// Stable identifier of the index, recorded in the schema registry like
+    /// [`Schema::ID`].
+    const ID: Option<&'static str> = None;
+
+    /// Whether each index key may belong to one entry only.
+    ///
+    /// Unique indexes use another entry layout, so changing this requires
+    /// rebuilding the index.
+    const UNIQUE: bool = false;
+
+    /// The type of the index keys.
+    type Key: KeyCodec<S>;
+
+    /// Derives the index key of a value, or `None` to leave the value out
+    /// of the index.
+    fn index_key(value: &DecodedValue<S>) -> Option<Self::Key>;
+}
+
+/// An index tree attached to a tree, with its schema and index types erased.
+pub(crate) trait IndexTree: Debug + Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn tree(&self) -> &Tree;
+
+    /// Returns the raw index entry key of the entry `primary_key` holding
+    /// the raw value `value`, if the value is indexed.
+    fn entry_key(
+        &self,
+        primary_key: &[u8],
+        value: &IVec,
+        ctx: &CodecContext<'_>,
+    ) -> Result<Option<Vec<u8>>>;
+
+    /// Returns the error for writing a second entry with the index key of
+    /// unique index entry `entry_key`.
+    fn unique_violation(&self, entry_key: &[u8]) -> Error;
+
+    fn is_unique(&self) -> bool;
+}
+
+#[derive(Debug)]
+struct IndexHandle<S, I> {
+    tree: Tree,
+    _phantom: PhantomData<fn() -> (S, I)>,
+}
+
+impl<S: Schema, I: Index<S>> IndexTree for IndexHandle<S, I> {
+    fn name(&self) -> &'static str {
+        I::TREE_NAME.0
+    }
+
+    fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    fn entry_key(
+        &self,
+        primary_key: &[u8],
+        value: &IVec,
+        ctx: &CodecContext<'_>,
+    ) -> Result<Option<Vec<u8>>> {
+        let value = S::Value::decode_value_with(value.clone(), &ctx.with_key(primary_key))?;
+        let Some(index_key) = I::index_key(&value) else {
+            return Ok(None);
+        };
+        // The self-delimiting index key is followed by the primary key, so
+        // entries sharing an index key stay distinct and sort by primary key.
+        let mut entry = Vec::new();
+        index_key.encode_key_component(&mut entry)?;
+        // Entries of a unique index are keyed by the index key alone.
+        if !I::UNIQUE {
+            entry.extend_from_slice(primary_key);
+        }
+        Ok(Some(entry))
+    }
+
+    fn unique_violation(&self, entry_key: &[u8]) -> Error {
+        let key = I::Key::decode_key_component(entry_key)
+            .and_then(|(key, _)| key.encode_key())
+            .unwrap_or_else(|_| entry_key.to_vec());
+        Error::UniqueViolation {
+            schema: S::TREE_NAME.0,
+            index: I::TREE_NAME.0,
+            key,
+        }
+    }
+
+    fn is_unique(&self) -> bool {
+        I::UNIQUE
+    }
+}
+
+/// The indexes attached to a tree, shared by every handle of the tree.
+///
+/// Writes hold the read lock until they commit, so attaching an index waits
+/// for the writes in progress and its initial build sees all of them.
+#[derive(Debug, Default)]
+pub(crate) struct TreeIndexes {
+    attached: RwLock<AttachedIndexes>,
+}
+
+impl TreeIndexes {
+    /// Creates the index set of a tree with the indexes `names` recorded as
+    /// attached to it, none of which is loaded yet.
+    pub(crate) fn recorded(names: Vec<String>) -> Self {
+        Self {
+            attached: RwLock::new(AttachedIndexes {
+                loaded: Vec::new(),
+                not_loaded: names,
+            }),
+        }
+    }
+
+    /// Locks the attached indexes for a write to the tree.
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, AttachedIndexes> {
+        self.attached.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Locks the attached indexes against writes to the tree.
+    fn write(&self) -> RwLockWriteGuard<'_, AttachedIndexes> {
+        self.attached
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Forgets every attached index, after they were detached from the tree.
+    pub(crate) fn detach_all(&self) {
+        *self.write() = AttachedIndexes::default();
+    }
+}
+
+/// The indexes attached to a tree.
+#[derive(Debug, Default)]
+pub(crate) struct AttachedIndexes {
+    loaded: Vec<Arc<dyn IndexTree>>,
+    /// Tree names of the indexes recorded as attached whose types were not
+    /// given to [`IndexedTree::with_index`] yet, so writes cannot maintain
+    /// them.
+    not_loaded: Vec<String>,
+}
+
+impl AttachedIndexes {
+    /// Returns `true` if no index is attached.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.loaded.is_empty() && self.not_loaded.is_empty()
+    }
+
+    /// Returns the trees of the loaded indexes, in the order
+    /// [`AttachedIndexes::views`] expects their views.
+    pub(crate) fn trees(&self) -> impl Iterator<Item = &Tree> {
+        self.loaded.iter().map(|index| index.tree())
+    }
+
+    /// Pairs the loaded indexes with their transactional views, taken from
+    /// `views` in the order of [`AttachedIndexes::trees`].
+    pub(crate) fn views<'v>(
+        &self,
+        views: &mut impl Iterator<Item = &'v TransactionalTree>,
+    ) -> IndexViews {
+        IndexViews {
+            loaded: self
+                .loaded
+                .iter()
+                .map(|index| {
+                    let view = views.next().expect("index tree view");
+                    (view.clone(), index.clone())
+                })
+                .collect(),
+            not_loaded: self.not_loaded.first().cloned(),
+        }
+    }
+
+    fn loaded(&self, name: &str) -> Option<&Arc<dyn IndexTree>> {
+        self.loaded.iter().find(|index| index.name() == name)
+    }
+
+    fn load(&mut self, index: Arc<dyn IndexTree>) {
+        self.not_loaded.retain(|name| name != index.name());
+        self.loaded.retain(|loaded| loaded.name() != index.name());
+        self.loaded.push(index);
+    }
+}
+
+/// Transactional views of the indexes attached to a tree.
+#[derive(Clone, Default)]
+pub(crate) struct IndexViews {
+    loaded: Vec<(TransactionalTree, Arc<dyn IndexTree>)>,
+    not_loaded: Option<String>,
+}
+
+impl IndexViews {
+    /// Writes or removes the raw value of `key` in `primary`, moving its
+    /// index entries from the previous value to the new one. Returns the
+    /// previous raw value.
+    ///
+    /// Fails with [`Error::IndexNotLoaded`] without writing if an index
+    /// attached to the tree is not loaded.
+    pub(crate) fn write(
+        &self,
+        schema: &'static str,
+        primary: &TransactionalTree,
+        key: &[u8],
+        value: Option<IVec>,
+        ctx: &CodecContext<'_>,
+    ) -> Result<Option<IVec>> {
+        if let Some(index) = &self.not_loaded {
+            return Err(Error::IndexNotLoaded {
+                schema,
+                index: index.clone(),
+            });
+        }
+        let old = match &value {
+            Some(value) => primary.insert(key, value.clone())?,
+            None => primary.remove(key)?,
+        };
+
+        for (tree, index) in &self.loaded {
+            let entry_key = |value: Option<&IVec>| {
+                value
+                    .map(|value| index.entry_key(key, value, ctx))
+                    .transpose()
+                    .map(Option::flatten)
+            };
+            let old_entry = entry_key(old.as_ref())?;
+            let new_entry = entry_key(value.as_ref())?;
+            if old_entry == new_entry {
+                continue;
+            }
+            if let Some(old_entry) = old_entry {
+                tree.remove(old_entry)?;
+            }
+            if let Some(new_entry) = new_entry {
+                // Reading the entry makes concurrent writes of the same index
+                // key conflict, so only one of them can commit.
+                if index.is_unique()
+                    && let Some(existing) = tree.get(&new_entry)?
+                    && existing != key
+                {
+                    return Err(index.unique_violation(&new_entry));
+                }
+                tree.insert(new_entry, key)?;
+            }
+        }
+        Ok(old)
+    }
+}
+
+/// Brings the tree of `index` in line with the entries of `tree` in one
+/// atomic batch, returning the number of indexed entries.
+///
+/// Fails without writing if two entries share a key of a unique index.
+fn sync_index<S: Schema>(tree: &SledTree<S>, index: &dyn IndexTree) -> Result<usize> {
+    let ctx = tree.ctx();
+    let mut expected = BTreeMap::new();
+    for entry in tree.inner.iter() {
+        let (key, value) = entry?;
+        if let Some(entry_key) = index.entry_key(&key, &value, &ctx)?
+            && expected.insert(entry_key.clone(), key).is_some()
+        {
+            return Err(index.unique_violation(&entry_key));
+        }
+    }
+    let indexed = expected.len();
+
+    let mut batch = Batch::default();
+    let mut changed = false;
+    for entry in index.tree().iter() {
+        let (entry_key, primary_key) = entry?;
+        match expected.get(&*entry_key) {
+            Some(expected_key) if *expected_key == primary_key => {
+                expected.remove(&*entry_key);
+            }
+            // Entries pointing elsewhere are replaced by the inserts below.
+            Some(_) => {}
+            None => {
+                batch.remove(entry_key);
+                changed = true;
+            }
+        }
+    }
+    for (entry_key, primary_key) in expected {
+        batch.insert(entry_key, primary_key);
+        changed = true;
+    }
+    if changed {
+        index.tree().apply_batch(batch)?;
+        tree.flusher.after_write(index.tree())?;
+    }
+    Ok(indexed)
+}
+
+/// Returns the smallest byte string greater than every string starting
+/// with `prefix`, or `None` if there is none.
+fn prefix_end(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|&byte| byte != u8::MAX)?;
+    let mut end = prefix[..=last].to_vec();
+    end[last] += 1;
+    Some(end)
+}
+
+/// A raw range over index entries.
+type EntryRange = (Bound<Vec<u8>>, Bound<Vec<u8>>);
+
+/// Converts a typed index key range to a raw range over index entries.
+fn entry_range<S, K, R>(range: R) -> Result<EntryRange>
+where
+    S: Schema,
+    K: KeyCodec<S>,
+    R: RangeBounds<K>,
+{
+    let component = |key: &K| -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        key.encode_key_component(&mut buf)?;
+        Ok(buf)
+    };
+    let start = match range.start_bound() {
+        Bound::Included(key) => Bound::Included(component(key)?),
+        Bound::Excluded(key) => {
+            let prefix = component(key)?;
+            match prefix_end(&prefix) {
+                Some(end) => Bound::Included(end),
+                // No entry sorts after those of the largest index key.
+                None => return Ok((Bound::Included(prefix.clone()), Bound::Excluded(prefix))),
+            }
+        }
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(key) => match prefix_end(&component(key)?) {
+            Some(end) => Bound::Excluded(end),
+            None => Bound::Unbounded,
+        },
+        Bound::Excluded(key) => Bound::Excluded(component(key)?),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    Ok((start, end))
+}
+
+/// Maps an error raised inside a transaction so that sled retries conflicts.
+pub(crate) fn conflictable(err: Error) -> ConflictableTransactionError<Error> {
+    match err {
+        Error::TransactionError(UnabortableTransactionError::Conflict) => {
+            ConflictableTransactionError::Conflict
+        }
+        Error::TransactionError(UnabortableTransactionError::Storage(err)) => {
+            ConflictableTransactionError::Storage(err)
+        }
+        err => ConflictableTransactionError::Abort(err),
+    }
+}
+
+/// A typed tree with secondary indexes attached, which it can be queried by.
+///
+/// Created with [`SledDb::get_indexed_tree`](crate::SledDb::get_indexed_tree).
+/// Attaching an index with [`IndexedTree::with_index`] makes every write to
+/// the tree update it in the same transaction, including writes through
+/// plain [`SledTree`] handles and [`SledTransactionalTree`] views of the
+/// schema, so an index never disagrees with the entries it points to.
+///
+/// Attachments are recorded in the database. After reopening it, writes to
+/// the tree fail with [`Error::IndexNotLoaded`] until every attached index
+/// is attached again, as only its type knows how to derive index keys.
+pub struct IndexedTree<S: Schema> {
+    tree: SledTree<S>,
+    db: Db,
+    indexes: Vec<Arc<dyn IndexTree>>,
+}
+
+impl<S: Schema> Debug for IndexedTree<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexedTree")
+            .field("tree_name", &S::TREE_NAME.0)
+            .field("indexes", &self.indexes)
+            .finish()
+    }
+}
+
+impl<S: Schema> Clone for IndexedTree<S> {
+    fn clone(&self) -> Self {
+        Self {
+            tree: self.tree.clone(),
+            db: self.db.clone(),
+            indexes: self.indexes.clone(),
+        }
+    }
+}
+
+impl<S: Schema> IndexedTree<S> {
+    /// Wraps `tree`, whose index trees are opened in `db`.
+    pub(crate) fn new(tree: SledTree<S>, db: Db) -> Self {
+        Self {
+            tree,
+            db,
+            indexes: Vec::new(),
+        }
+    }
+
+    /// Attaches index `I` to the tree, maintaining it on every write from
+    /// now on, and makes it available to queries through this handle.
+    ///
+    /// The index tree is registered in the schema registry like a schema
+    /// tree, and the attachment is recorded for the tree. The first time
+    /// the index is attached in a process, it is checked against the entries
+    /// of the tree and repaired if they disagree, see
+    /// [`IndexedTree::rebuild_index`]; it is only registered and attached
+    /// once that succeeds.
+    ///
+    /// Attaching waits for the writes to the tree in progress, so do not
+    /// call it within a transaction over the tree.
+    pub fn with_index<I: Index<S>>(mut self) -> Result<Self>
+    where
+        S: 'static,
+    {
+        let tree_name = I::TREE_NAME.into_inner();
+        if tree_name.starts_with(RESERVED_PREFIX) {
+            return Err(Error::ReservedTreeName(tree_name));
+        }
+        let registered = registry::check_index::<S, I>(&self.db)?;
+
+        let mut attached = self.tree.indexes.write();
+        let index = match attached.loaded(tree_name) {
+            Some(index) => index.clone(),
+            None => {
+                let index: Arc<dyn IndexTree> = Arc::new(IndexHandle::<S, I> {
+                    tree: self.db.open_tree(tree_name)?,
+                    _phantom: PhantomData,
+                });
+                sync_index(&self.tree, &*index)?;
+                if !registered {
+                    registry::check_or_register_index::<S, I>(&self.db)?;
+                }
+                registry::attach_index::<S, I>(&self.db)?;
+                attached.load(index.clone());
+                index
+            }
+        };
+        drop(attached);
+
+        self.indexes.retain(|attached| attached.name() != tree_name);
+        self.indexes.push(index);
+        Ok(self)
+    }
+
+    /// Rebuilds index `I` from the entries of the tree, returning the number
+    /// of indexed entries.
+    ///
+    /// Writes to the tree wait while the entries are read, and the index is
+    /// then updated in one atomic batch. If two entries share a key of a
+    /// unique index, the rebuild fails and the index is left unchanged.
+    pub fn rebuild_index<I: Index<S>>(&self) -> Result<usize> {
+        let index = self.index::<I>()?;
+        let _attached = self.tree.indexes.write();
+        sync_index(&self.tree, index)
+    }
+
+    /// Returns the index `I` attached through this handle.
+    fn index<I: Index<S>>(&self) -> Result<&dyn IndexTree> {
+        self.indexes
+            .iter()
+            .find(|index| index.name() == I::TREE_NAME.0)
+            .map(|index| &**index)
+            .ok_or(Error::IndexNotFound {
+                schema: S::TREE_NAME.0,
+                index: I::TREE_NAME.0,
+            })
+    }
+
+    /// Returns the plain handle of the tree, whose writes maintain the
+    /// attached indexes too, for use in transactions over several trees.
+    pub fn tree(&self) -> &SledTree<S> {
+        &self.tree
+    }
+
+    /// Inserts a key-value pair and updates every index.
+    pub fn insert(&self, key: &S::Key, value: &S::Value) -> Result<()> {
+        self.tree.insert(key, value)
+    }
+
+    /// Removes a key-value pair and its index entries.
+    pub fn remove(&self, key: &S::Key) -> Result<()> {
+        self.tree.remove(key)
+    }
+
+    /// Removes a key-value pair and its index entries and returns the
+    /// previous value.
+    pub fn take(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
+        self.tree.take(key)
+    }
+
+    /// Retrieves a value for the given key, which may be given in any form
+    /// the key type borrows as.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<DecodedValue<S>>>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        self.tree.get(key)
+    }
+
+    /// Returns `true` if the tree contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        self.tree.contains_key(key)
+    }
+
+    /// Returns true if the tree contains no key-value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// Returns an iterator over all key-value pairs in the tree.
+    pub fn iter(&self) -> SledTreeIter<S> {
+        self.tree.iter()
+    }
+
+    /// Returns an iterator over key-value pairs within the specified range.
+    pub fn range<R>(&self, range: R) -> Result<SledTreeIter<S>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        self.tree.range(range)
+    }
+
+    /// Returns an iterator over the entries whose index key under `I` is
+    /// `key`, in primary key order.
+    ///
+    /// The key may be given in any form the index key type borrows as, such
+    /// as a `str` for `String` index keys.
+    pub fn get_by_index<I, Q>(&self, key: &Q) -> Result<IndexIter<S>>
+    where
+        I: Index<S>,
+        I::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        let index = self.index::<I>()?;
+        let mut prefix = Vec::new();
+        key.encode_lookup_component(&mut prefix)?;
+        Ok(self.index_iter(index.tree().scan_prefix(prefix)))
+    }
+
+    /// Returns an iterator over the entries whose index key under `I` lies
+    /// within `range`, ordered by index key and then primary key.
+    pub fn range_by_index<I, R>(&self, range: R) -> Result<IndexIter<S>>
+    where
+        I: Index<S>,
+        R: RangeBounds<I::Key>,
+    {
+        let index = self.index::<I>()?;
+        let range = entry_range::<S, I::Key, R>(range)?;
+        Ok(self.index_iter(index.tree().range(range)))
+    }
+
+    fn index_iter(&self, entries: Iter) -> IndexIter<S> {
+        IndexIter {
+            entries,
+            tree: self.tree.clone(),
+        }
+    }
+}
+
+impl<S: Schema> SledTransactional for IndexedTree<S> {
+    type View = IndexedTransactionalTree<S>;
+
+    fn transaction<F, R, E>(&self, func: F) -> TransactionResult<R, E>
+    where
+        F: Fn(Self::View) -> ConflictableTransactionResult<R, E>,
+    {
+        (&self.tree,).transaction(|(tree,)| func(IndexedTransactionalTree { tree }))
+    }
+}
+
+/// Transactional view of an [`IndexedTree`], updating its indexes within
+/// the transaction.
+pub struct IndexedTransactionalTree<S: Schema> {
+    tree: SledTransactionalTree<S>,
+}
+
+impl<S: Schema> Debug for IndexedTransactionalTree<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexedTransactionalTree")
+            .field("tree_name", &S::TREE_NAME.0)
+            .field("schema", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: Schema> IndexedTransactionalTree<S> {
+    /// Inserts a key-value pair and updates every index in the transaction.
+    pub fn insert(&self, key: &S::Key, value: &S::Value) -> Result<()> {
+        self.tree.insert(key, value)
+    }
+
+    /// Retrieves a value for the given key within the transaction.
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<DecodedValue<S>>>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        self.tree.get(key)
+    }
+
+    /// Returns `true` if the tree contains a value for the specified key.
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        self.tree.contains_key(key)
+    }
+
+    /// Removes a key-value pair and its index entries in the transaction.
+    pub fn remove(&self, key: &S::Key) -> Result<()> {
+        self.tree.remove(key)
+    }
+
+    /// Removes a key-value pair and its index entries in the transaction and
+    /// returns the previous value.
+    pub fn take(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
+        self.tree.take(key)
+    }
+}
+
+/// An iterator over the entries found through a secondary index.
+pub struct IndexIter<S: Schema> {
+    entries: Iter,
+    tree: SledTree<S>,
+}
+
+impl<S: Schema> Debug for IndexIter<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IndexIter")
+            .field("tree_name", &S::TREE_NAME.0)
+            .field("schema", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: Schema> Iterator for IndexIter<S> {
+    type Item = Result<(S::Key, DecodedValue<S>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let primary_key = match self.entries.next()? {
+                Ok((_, primary_key)) => primary_key,
+                Err(err) => return Some(Err(err.into())),
+            };
+            match self.tree.inner.get(&primary_key) {
+                Ok(Some(value)) => return Some(self.tree.decode_entry((primary_key, value))),
+                // Removed since the index entry was read.
+                Ok(None) => continue,
+                Err(err) => return Some(Err(err.into())),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use sled::transaction::TransactionError;
+
+    use super::*;
+    use crate::{SledDb, test_utils::*};
+
+    /// Indexes test values by name.
+    #[derive(Debug)]
+    struct ByName;
+
+    impl Index<TestSchema1> for ByName {
+        const TREE_NAME: TreeName = TreeName("test1_by_name");
+        type Key = String;
+
+        fn index_key(value: &TestValue) -> Option<String> {
+            Some(value.name.clone())
+        }
+    }
+
+    /// Indexes test values by id, leaving out those with id 0.
+    #[derive(Debug)]
+    struct ById;
+
+    impl Index<TestSchema1> for ById {
+        const TREE_NAME: TreeName = TreeName("test1_by_id");
+        type Key = u32;
+
+        fn index_key(value: &TestValue) -> Option<u32> {
+            (value.id != 0).then_some(value.id)
+        }
+    }
+
+    /// Requires every test value to have its own name.
+    #[derive(Debug)]
+    struct UniqueName;
+
+    impl Index<TestSchema1> for UniqueName {
+        const TREE_NAME: TreeName = TreeName("test1_unique_name");
+        const UNIQUE: bool = true;
+        type Key = String;
+
+        fn index_key(value: &TestValue) -> Option<String> {
+            Some(value.name.clone())
+        }
+    }
+
+    /// Declares an index on the tree of [`TestSchema2`].
+    #[derive(Debug)]
+    struct Squatter;
+
+    impl Index<TestSchema1> for Squatter {
+        const TREE_NAME: TreeName = TestSchema2::TREE_NAME;
+        type Key = u32;
+
+        fn index_key(value: &TestValue) -> Option<u32> {
+            Some(value.id)
+        }
+    }
+
+    fn indexed_tree(db: &SledDb) -> IndexedTree<TestSchema1> {
+        db.get_indexed_tree::<TestSchema1>()
+            .unwrap()
+            .with_index::<ByName>()
+            .unwrap()
+            .with_index::<ById>()
+            .unwrap()
+    }
+
+    fn primary_keys(iter: Result<IndexIter<TestSchema1>>) -> Vec<u32> {
+        iter.unwrap().map(|entry| entry.unwrap().0).collect()
+    }
+
+    #[test]
+    fn test_writes_maintain_indexes() {
+        let db = create_test_db().unwrap();
+        let tree = indexed_tree(&db);
+        tree.insert(&1, &TestValue::new(7, "Alice")).unwrap();
+        tree.insert(&2, &TestValue::new(7, "Bob")).unwrap();
+        tree.insert(&3, &TestValue::new(9, "Alice")).unwrap();
+
+        assert_eq!(
+            primary_keys(tree.get_by_index::<ByName, _>("Alice")),
+            vec![1, 3]
+        );
+        assert_eq!(primary_keys(tree.get_by_index::<ById, _>(&7)), vec![1, 2]);
+
+        // Updating a value moves its index entries.
+        tree.insert(&1, &TestValue::new(9, "Carol")).unwrap();
+        assert_eq!(
+            primary_keys(tree.get_by_index::<ByName, _>("Alice")),
+            vec![3]
+        );
+        assert_eq!(primary_keys(tree.get_by_index::<ById, _>(&9)), vec![1, 3]);
+
+        tree.remove(&3).unwrap();
+        let taken = tree.take(&2).unwrap().unwrap();
+        assert_test_values_eq(&TestValue::new(7, "Bob"), &taken);
+        assert!(primary_keys(tree.get_by_index::<ByName, _>("Alice")).is_empty());
+        assert_eq!(primary_keys(tree.get_by_index::<ById, _>(&9)), vec![1]);
+        assert_eq!(db.inner_db().open_tree("test1_by_id").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_range_by_index() {
+        let db = create_test_db().unwrap();
+        let tree = indexed_tree(&db);
+        for (key, id) in [(1, 30), (2, 10), (3, 20), (4, 0), (5, u32::MAX), (6, 20)] {
+            tree.insert(&key, &TestValue::new(id, "x")).unwrap();
+        }
+
+        assert_eq!(
+            primary_keys(tree.range_by_index::<ById, _>(..)),
+            vec![2, 3, 6, 1, 5]
+        );
+        assert_eq!(
+            primary_keys(tree.range_by_index::<ById, _>(20..=30)),
+            vec![3, 6, 1]
+        );
+        assert_eq!(
+            primary_keys(tree.range_by_index::<ById, _>(10..30)),
+            vec![2, 3, 6]
+        );
+        assert_eq!(
+            primary_keys(tree.range_by_index::<ById, _>((Bound::Excluded(20), Bound::Unbounded))),
+            vec![1, 5]
+        );
+        assert_eq!(
+            primary_keys(tree.range_by_index::<ById, _>(30..=u32::MAX)),
+            vec![1, 5]
+        );
+        assert!(
+            primary_keys(
+                tree.range_by_index::<ById, _>((Bound::Excluded(u32::MAX), Bound::Unbounded))
+            )
+            .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_aborted_transaction_leaves_indexes_alone() {
+        let db = create_test_db().unwrap();
+        let tree = indexed_tree(&db);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+
+        let result = tree.transaction(|tx| {
+            tx.insert(&2, &TestValue::bob())?;
+            tx.remove(&1)?;
+            Err::<(), _>(ConflictableTransactionError::Abort(Error::abort(
+                std::fmt::Error,
+            )))
+        });
+        assert!(result.is_err());
+
+        assert!(tree.get(&2).unwrap().is_none());
+        assert_eq!(
+            primary_keys(tree.get_by_index::<ByName, _>("Alice")),
+            vec![1]
+        );
+        assert!(primary_keys(tree.get_by_index::<ByName, _>("Bob")).is_empty());
+    }
+
+    #[test]
+    fn test_attaching_index_builds_it_from_existing_entries() {
+        let db = create_test_db().unwrap();
+        let plain = db.get_tree::<TestSchema1>().unwrap();
+        plain.insert(&1, &TestValue::alice()).unwrap();
+        plain.insert(&2, &TestValue::new(0, "Nobody")).unwrap();
+
+        let tree = indexed_tree(&db);
+        assert_eq!(primary_keys(tree.get_by_index::<ById, _>(&1)), vec![1]);
+        assert_eq!(primary_keys(tree.range_by_index::<ById, _>(..)), vec![1]);
+        assert_eq!(
+            primary_keys(tree.get_by_index::<ByName, _>("Nobody")),
+            vec![2]
+        );
+        assert_eq!(tree.rebuild_index::<ByName>().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_reattaching_index_repairs_it() {
+        let db = create_test_db().unwrap();
+        let tree = indexed_tree(&db);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        tree.insert(&2, &TestValue::bob()).unwrap();
+
+        // Bypass the index: drop one entry and add another one.
+        tree.tree()
+            .inner
+            .remove(<u32 as KeyCodec<TestSchema1>>::encode_key(&2).unwrap())
+            .unwrap();
+        let key = <u32 as KeyCodec<TestSchema1>>::encode_key(&3).unwrap();
+        let value = ValueCodec::<TestSchema1>::encode_value(&TestValue::charlie()).unwrap();
+        tree.tree().inner.insert(key, value).unwrap();
+
+        let reopened = SledDb::new(db.inner_db().clone()).unwrap();
+        let tree = indexed_tree(&reopened);
+        assert!(primary_keys(tree.get_by_index::<ByName, _>("Bob")).is_empty());
+        assert_eq!(
+            primary_keys(tree.get_by_index::<ByName, _>("Charlie")),
+            vec![3]
+        );
+        assert_eq!(tree.rebuild_index::<ByName>().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_index_trees_are_registered() {
+        let db = create_test_db().unwrap();
+        db.get_tree::<TestSchema2>().unwrap();
+        indexed_tree(&db);
+
+        assert!(matches!(
+            db.get_indexed_tree::<TestSchema1>()
+                .unwrap()
+                .with_index::<Squatter>(),
+            Err(Error::SchemaCollision { .. })
+        ));
+
+        let tree = db.get_indexed_tree::<TestSchema1>().unwrap();
+        assert!(matches!(
+            tree.get_by_index::<ByName, _>("Alice"),
+            Err(Error::IndexNotFound {
+                schema: "test1",
+                index: "test1_by_name"
+            })
+        ));
+    }
+
+    fn unique_tree(db: &SledDb) -> IndexedTree<TestSchema1> {
+        db.get_indexed_tree::<TestSchema1>()
+            .unwrap()
+            .with_index::<UniqueName>()
+            .unwrap()
+    }
+
+    fn assert_violation<T: Debug>(result: Result<T>, name: &str) {
+        match result {
+            Err(Error::UniqueViolation { schema, index, key }) => {
+                assert_eq!(schema, TestSchema1::TREE_NAME.0);
+                assert_eq!(index, UniqueName::TREE_NAME.0);
+                assert_eq!(key, name.as_bytes());
+            }
+            other => panic!("expected unique violation, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_unique_index_rejects_duplicates() {
+        let db = create_test_db().unwrap();
+        let tree = unique_tree(&db);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+
+        assert_violation(tree.insert(&2, &TestValue::new(2, "Alice")), "Alice");
+        assert!(tree.get(&2).unwrap().is_none());
+
+        // Rewriting the owner of a key is not a duplicate.
+        tree.insert(&1, &TestValue::new(7, "Alice")).unwrap();
+        tree.insert(&1, &TestValue::bob()).unwrap();
+        tree.insert(&2, &TestValue::new(2, "Alice")).unwrap();
+        assert_eq!(
+            primary_keys(tree.get_by_index::<UniqueName, _>("Alice")),
+            vec![2]
+        );
+        assert_eq!(
+            primary_keys(tree.range_by_index::<UniqueName, _>(..)),
+            vec![2, 1]
+        );
+
+        tree.remove(&1).unwrap();
+        tree.insert(&3, &TestValue::bob()).unwrap();
+    }
+
+    #[test]
+    fn test_unique_index_in_transaction() {
+        let db = create_test_db().unwrap();
+        let tree = unique_tree(&db);
+
+        let result = tree.transaction(|tx| {
+            tx.insert(&1, &TestValue::alice())?;
+            tx.insert(&2, &TestValue::new(2, "Alice"))?;
+            Ok::<_, ConflictableTransactionError<Error>>(())
+        });
+        match result {
+            Err(TransactionError::Abort(err)) => assert_violation::<()>(Err(err), "Alice"),
+            other => panic!("expected aborted transaction, got {other:?}"),
+        }
+        assert!(tree.is_empty());
+
+        // A key freed earlier in the same transaction can be reused.
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        tree.transaction(|tx| {
+            tx.remove(&1)?;
+            tx.insert(&2, &TestValue::new(2, "Alice"))?;
+            Ok::<_, ConflictableTransactionError<Error>>(())
+        })
+        .unwrap();
+        assert_eq!(
+            primary_keys(tree.get_by_index::<UniqueName, _>("Alice")),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_unique_inserts() {
+        let db = create_test_db().unwrap();
+        let tree = unique_tree(&db);
+
+        let successes = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|key| {
+                    let tree = tree.clone();
+                    scope.spawn(move || tree.insert(&key, &TestValue::new(key, "Same")).is_ok())
+                })
+                .collect();
+            handles
+                .into_iter()
+                .filter_map(|handle| handle.join().unwrap().then_some(()))
+                .count()
+        });
+
+        assert_eq!(successes, 1);
+        assert_eq!(tree.iter().count(), 1);
+        assert_eq!(
+            primary_keys(tree.get_by_index::<UniqueName, _>("Same")).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_unique_index_over_duplicate_data_fails_to_build() {
+        let db = create_test_db().unwrap();
+        let plain = db.get_tree::<TestSchema1>().unwrap();
+        plain.insert(&1, &TestValue::alice()).unwrap();
+        plain.insert(&2, &TestValue::new(2, "Alice")).unwrap();
+
+        assert_violation(
+            db.get_indexed_tree::<TestSchema1>()
+                .unwrap()
+                .with_index::<UniqueName>(),
+            "Alice",
+        );
+    }
+
+    #[test]
+    fn test_plain_handles_maintain_indexes() {
+        let db = create_test_db().unwrap();
+        // Handles obtained before the index was attached maintain it too.
+        let plain = db.get_tree::<TestSchema1>().unwrap();
+        let tree = unique_tree(&db);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+
+        assert_violation(plain.insert(&2, &TestValue::new(2, "Alice")), "Alice");
+        let fresh = db.get_tree::<TestSchema1>().unwrap();
+        assert_violation(fresh.insert(&2, &TestValue::new(2, "Alice")), "Alice");
+        assert!(plain.get(&2).unwrap().is_none());
+
+        plain.insert(&2, &TestValue::bob()).unwrap();
+        let mut batch = plain.batch();
+        batch.remove(1).unwrap();
+        batch.insert(3, TestValue::alice()).unwrap();
+        plain.apply_batch(batch).unwrap();
+        assert!(
+            plain
+                .compare_and_swap(2, Some(TestValue::bob()), Some(TestValue::charlie()))
+                .unwrap()
+                .is_ok()
+        );
+        assert_eq!(
+            primary_keys(tree.range_by_index::<UniqueName, _>(..)),
+            vec![3, 2]
+        );
+
+        assert_eq!(plain.pop_min().unwrap().unwrap().0, 2);
+        assert_eq!(
+            primary_keys(tree.range_by_index::<UniqueName, _>(..)),
+            vec![3]
+        );
+    }
+
+    #[test]
+    fn test_multi_tree_transactions_maintain_indexes() {
+        let db = create_test_db().unwrap();
+        let tree = unique_tree(&db);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        let other = db.get_tree::<TestSchema2>().unwrap();
+
+        let result = (tree.tree(), &other).transaction(|(users, other)| {
+            other.insert(&1, &TestValue::bob())?;
+            users.insert(&2, &TestValue::new(2, "Alice"))?;
+            Ok::<_, ConflictableTransactionError<Error>>(())
+        });
+        match result {
+            Err(TransactionError::Abort(err)) => assert_violation::<()>(Err(err), "Alice"),
+            other => panic!("expected aborted transaction, got {other:?}"),
+        }
+        assert!(other.is_empty());
+
+        (tree.tree(), &other)
+            .transaction(|(users, other)| {
+                other.insert(&1, &TestValue::bob())?;
+                users.insert(&2, &TestValue::bob())?;
+                Ok::<_, ConflictableTransactionError<Error>>(())
+            })
+            .unwrap();
+        assert_eq!(
+            primary_keys(tree.get_by_index::<UniqueName, _>("Bob")),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_attached_indexes_must_be_loaded_before_writing() {
+        let db = create_test_db().unwrap();
+        unique_tree(&db).insert(&1, &TestValue::alice()).unwrap();
+
+        // The attachment outlives the `SledDb` that made it.
+        let reopened = SledDb::new(db.inner_db().clone()).unwrap();
+        let plain = reopened.get_tree::<TestSchema1>().unwrap();
+        assert_test_values_eq(&TestValue::alice(), &plain.get(&1).unwrap().unwrap());
+        match plain.insert(&2, &TestValue::bob()) {
+            Err(Error::IndexNotLoaded { schema, index }) => {
+                assert_eq!(schema, TestSchema1::TREE_NAME.0);
+                assert_eq!(index, UniqueName::TREE_NAME.0);
+            }
+            other => panic!("expected index not loaded error, got {other:?}"),
+        }
+
+        unique_tree(&reopened);
+        assert_violation(plain.insert(&2, &TestValue::new(2, "Alice")), "Alice");
+        plain.insert(&2, &TestValue::bob()).unwrap();
+    }
+
+    #[test]
+    fn test_failed_rebuild_leaves_index_unchanged() {
+        let db = create_test_db().unwrap();
+        let tree = unique_tree(&db);
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        tree.insert(&2, &TestValue::bob()).unwrap();
+
+        // Bypass the index to store a duplicate.
+        let key = <u32 as KeyCodec<TestSchema1>>::encode_key(&3).unwrap();
+        let value = ValueCodec::<TestSchema1>::encode_value(&TestValue::alice()).unwrap();
+        tree.tree().inner.insert(key, value).unwrap();
+
+        assert_violation(tree.rebuild_index::<UniqueName>(), "Alice");
+        assert_eq!(
+            primary_keys(tree.range_by_index::<UniqueName, _>(..)),
+            vec![1, 2]
+        );
+    }
+
+    /// A schema with signed keys, stored in the legacy layout.
+    #[derive(Debug)]
+    struct LegacySchema;
+
+    impl Schema for LegacySchema {
+        const TREE_NAME: TreeName = TreeName("legacy");
+        type Key = i64;
+        type Value = TestValue;
+    }
+
+    #[derive(Debug)]
+    struct LegacyByName;
+
+    impl Index<LegacySchema> for LegacyByName {
+        const TREE_NAME: TreeName = TreeName("legacy_by_name");
+        const UNIQUE: bool = true;
+        type Key = String;
+
+        fn index_key(value: &TestValue) -> Option<String> {
+            Some(value.name.clone())
+        }
+    }
+
+    #[test]
+    fn test_legacy_key_migration_moves_index_entries() {
+        let db = create_test_db().unwrap();
+        let plain = db.get_tree::<LegacySchema>().unwrap();
+        for key in [-1_i64, 1] {
+            let value = TestValue::new_with_name(key as u32);
+            let value = ValueCodec::<LegacySchema>::encode_value(&value).unwrap();
+            plain.inner.insert(key.to_be_bytes(), value).unwrap();
+        }
+        let tree = db
+            .get_indexed_tree::<LegacySchema>()
+            .unwrap()
+            .with_index::<LegacyByName>()
+            .unwrap();
+
+        assert_eq!(db.migrate_legacy_signed_keys::<LegacySchema>().unwrap(), 2);
+        let name = TestValue::new_with_name(-1_i64 as u32).name;
+        let found: Vec<i64> = tree
+            .get_by_index::<LegacyByName, _>(&name)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect();
+        assert_eq!(found, vec![-1]);
+        assert_eq!(tree.rebuild_index::<LegacyByName>().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_failed_index_build_is_retried() {
+        let db = create_test_db().unwrap();
+        let plain = db.get_tree::<TestSchema1>().unwrap();
+        plain.insert(&1, &TestValue::alice()).unwrap();
+        plain.insert(&2, &TestValue::new(2, "Alice")).unwrap();
+        let attach = || {
+            db.get_indexed_tree::<TestSchema1>()
+                .unwrap()
+                .with_index::<UniqueName>()
+        };
+        assert_violation(attach(), "Alice");
+        assert_violation(attach(), "Alice");
+
+        plain.remove(&2).unwrap();
+        plain.insert(&3, &TestValue::new(3, "zed")).unwrap();
+        let tree = attach().unwrap();
+        assert_eq!(
+            primary_keys(tree.get_by_index::<UniqueName, _>("zed")),
+            vec![3]
+        );
+        assert_eq!(
+            primary_keys(tree.get_by_index::<UniqueName, _>("Alice")),
+            vec![1]
+        );
+    }
+}
@@ -12,6 +12,19 @@
 //! - **Serialization**: Flexible codec system for efficient binary encoding
 //! - **Transactions**: Multi-table atomic operations
 //! - **Error Handling**: Comprehensive error types with proper error chaining
+//! - **Serde Adapters**: `Json`, `Bincode` and `Postcard` codecs behind the `serde-json`,
+//!   `bincode` and `postcard` features
+//! - **Checksums**: `Checksummed` value envelope that detects corrupted values, behind the
+//!   `checksum` feature
+//! - **Compression**: `Compressed` value codec wrapper, with LZ4 behind the `lz4` feature
+//! - **Encryption**: `Encrypted` value codec with pluggable key providers behind the
+//!   `encryption` feature
+//! - **Schema Evolution**: `Versioned` value envelope that upgrades values written by older
+//!   releases
+//! - **Async**: `Stream` subscribers, `flush_async` and async transaction retries behind
+//!   the `async` feature
+//! - **Derive Macros**: `Schema`, `KeyCodec` and `RkyvValueCodec` derives behind the
+//!   `derive` feature
 //!
 //! ## Example
 //!
@@ -59,8 +72,7 @@
 //! }
 //!
 //! fn main() -> Result<()> {
-//!     let sled_db = sled::open("mydb").unwrap();
-//!     let db = SledDb::new(sled_db)?;
+//!     let db = SledDb::builder().path("mydb").open()?;
 //!     let tree = db.get_tree::<UserSchema>()?;
 //!
 //!     let user = User {
@@ -78,24 +90,74 @@
 
 /// Batch operations for multiple key-value pairs.
 pub mod batch;
+/// Checksummed value envelope for detecting on-disk corruption.
+#[cfg(feature = "checksum")]
+pub mod checksum;
 /// Codec traits and errors for serialization/deserialization.
 pub mod codec;
+/// Transparent value compression codec.
+pub mod compression;
 /// Database wrapper around sled with type safety.
 pub mod db;
+/// Key providers and the `Encrypted` value codec for encryption at rest.
+pub mod encryption;
 /// Error types and utilities.
 pub mod error;
+/// Flush policies controlling when writes reach disk.
+pub mod flush;
+/// Secondary indexes maintained atomically with their tree.
+pub mod index;
+/// Numbered migration steps for evolving the trees of a database.
+pub mod migration;
+/// Cursor-based pagination over the entries of a tree.
+pub mod page;
+mod registry;
 /// Schema trait and tree name definitions.
 pub mod schema;
+/// Codec adapters for serde-compatible types, so serde and rkyv schemas can
+/// share one [`SledDb`].
+#[cfg(any(feature = "serde-json", feature = "bincode", feature = "postcard"))]
+pub mod serde_codec;
 /// Transaction support with retry policies.
 pub mod transaction;
 /// Type-safe tree operations.
 pub mod tree;
+/// Versioned value envelope with upgrade chains for schema evolution.
+pub mod versioned;
+/// Typed subscriptions to the changes of a tree.
+pub mod watch;
 
 #[cfg(test)]
 mod test_utils;
 
 // Re-export main types
-pub use codec::{CodecError, CodecResult, KeyCodec, RkyvView, ValueCodec};
-pub use db::SledDb;
+#[cfg(feature = "checksum")]
+pub use checksum::Checksummed;
+pub use codec::{
+    CodecError, CodecResult, KeyCodec, KeyLookup, KeyPrefix, Prefix, Rkyv, RkyvView, ValueCodec,
+};
+pub use db::{SledDb, SledDbBuilder};
+#[cfg(feature = "encryption")]
+pub use encryption::Encrypted;
+pub use encryption::{KeyProvider, StaticKeyProvider};
+pub use flush::FlushPolicy;
+pub use index::{Index, IndexedTree};
+pub use migration::{Migration, Migrator};
 pub use schema::{Schema, TreeName};
-pub use tree::SledTree;
+#[cfg(feature = "serde-json")]
+pub use serde_codec::Json;
+#[cfg(feature = "postcard")]
+pub use serde_codec::Postcard;
+#[cfg(feature = "bincode")]
+pub use serde_codec::{Bincode, BincodeOrdered};
+pub use tree::{CasResult, SledTree};
+pub use versioned::{Versioned, VersionedValue};
+// Re-export derive macros
+#[cfg(feature = "derive")]
+pub use typed_sled_derive::{KeyCodec, RkyvValueCodec, Schema};
+
+/// Items used by code generated by the derive macros. Not public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use sled::IVec;
+}
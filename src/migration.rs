@@ -0,0 +1,413 @@
+use std::fmt;
+
+use sled::{Transactional, Tree};
+
+use crate::{
+    Rkyv, Schema, SledDb, SledTree, TreeName,
+    error::{Error, Result},
+    registry,
+    tree::DecodedValue,
+};
+
+/// Runs one migration step.
+type MigrationFn = Box<dyn Fn(&Migrator<'_>) -> Result<()>>;
+
+/// A numbered step of a schema migration, run by [`SledDb::run_migrations`].
+///
+/// A step that fails is not recorded and runs again on the next call, as
+/// does a step interrupted by a crash before it was recorded. Steps built
+/// from the [`Migrator`] helpers write each tree atomically, but a step
+/// touching several trees is not atomic as a whole. Rewrites committed by
+/// an earlier run of the step are skipped when it runs again.
+pub struct Migration {
+    version: u64,
+    name: &'static str,
+    run: MigrationFn,
+}
+
+impl Migration {
+    /// Creates migration step `version`, which runs `run` when applied.
+    pub fn new(
+        version: u64,
+        name: &'static str,
+        run: impl Fn(&Migrator<'_>) -> Result<()> + 'static,
+    ) -> Self {
+        Self {
+            version,
+            name,
+            run: Box::new(run),
+        }
+    }
+
+    /// Returns the number identifying this step.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the name of this step.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl fmt::Debug for Migration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Migration")
+            .field("version", &self.version)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Handle given to a running [`Migration`] step.
+#[derive(Debug)]
+pub struct Migrator<'a> {
+    db: &'a SledDb,
+    /// Version of the running step.
+    version: u64,
+}
+
+impl Migrator<'_> {
+    /// Returns the database being migrated.
+    pub fn db(&self) -> &SledDb {
+        self.db
+    }
+
+    /// Opens the tree of schema `S` without checking the schema registry,
+    /// so that data stored by a schema that is no longer registered can be
+    /// read or written.
+    pub fn tree<S: Schema>(&self) -> Result<SledTree<S>> {
+        self.db.open_unchecked::<S>()
+    }
+
+    /// Converts every entry of the tree of `Old` into an entry of the tree
+    /// of `New` and registers `New`, returning the number of entries written.
+    ///
+    /// `convert` returns `None` to drop an entry. When both schemas share a
+    /// tree, its entries are replaced. Otherwise the entries are added to the
+    /// tree of `New` and the tree of `Old` is left alone; drop it with
+    /// [`Migrator::drop_tree`] to rename a tree.
+    ///
+    /// The entries and the registration of `New` are written in one
+    /// transaction, which also records the rewrite for the running step. If
+    /// the step runs again after being interrupted, the recorded rewrite is
+    /// skipped and `0` is returned.
+    ///
+    /// Indexes attached to the tree of `New` are detached in the same
+    /// transaction, as they describe the old entries. Attach them again with
+    /// [`IndexedTree::with_index`](crate::IndexedTree::with_index), which
+    /// rebuilds them.
+    pub fn rewrite_tree<Old, New, F>(&self, mut convert: F) -> Result<usize>
+    where
+        Old: Schema,
+        New: Schema,
+        F: FnMut(Old::Key, DecodedValue<Old>) -> Option<(New::Key, New::Value)>,
+    {
+        let rewrites = rewrites(self.db)?;
+        let mut marker = self.version.to_be_bytes().to_vec();
+        marker.extend_from_slice(New::TREE_NAME.0.as_bytes());
+        if rewrites.contains_key(&marker)? {
+            return Ok(0);
+        }
+
+        let old = self.tree::<Old>()?;
+        let new = self.tree::<New>()?;
+        let same_tree = Old::TREE_NAME == New::TREE_NAME;
+
+        let mut batch = new.batch();
+        let mut written = 0;
+        for entry in old.inner.iter() {
+            let (raw_key, raw_value) = entry?;
+            if same_tree {
+                batch.remove_raw(raw_key.clone());
+            }
+            let (key, value) = old.decode_entry((raw_key, raw_value))?;
+            if let Some((key, value)) = convert(key, value) {
+                // Inserts replace removals of the same key within the batch.
+                batch.insert(key, value)?;
+                written += 1;
+            }
+        }
+
+        let (registry, name, record) = registry::registration::<New>(self.db.inner_db())?;
+        let (attachments, indexes) =
+            registry::attached_indexes(self.db.inner_db(), New::TREE_NAME.0)?;
+        let batch = batch.to_sled();
+        let written = (&new.inner, &registry, &rewrites, &attachments)
+            .transaction(|(tree, registry, rewrites, attachments)| {
+                if rewrites.get(&marker)?.is_some() {
+                    return Ok(0);
+                }
+                tree.apply_batch(&batch)?;
+                registry.insert(name.as_slice(), record.as_slice())?;
+                for (attachment, index) in &indexes {
+                    attachments.remove(attachment)?;
+                    registry.remove(index.as_slice())?;
+                }
+                rewrites.insert(marker.as_slice(), &[])?;
+                Ok(written)
+            })
+            .map_err(Error::from_transaction)?;
+
+        self.db.registered::<New>();
+        self.db.detached_indexes::<New>();
+        new.flusher.after_write(&new.inner)?;
+        Ok(written)
+    }
+
+    /// Deletes the tree of schema `S` and its schema registration,
+    /// returning whether the tree existed.
+    pub fn drop_tree<S: Schema>(&self) -> Result<bool> {
+        self.db.drop_tree::<S>()
+    }
+}
+
+/// Reserved tree recording the applied migration steps by version.
+#[derive(Debug)]
+struct MigrationSchema;
+
+impl Schema for MigrationSchema {
+    const TREE_NAME: TreeName = TreeName("__typed_sled_migrations");
+    type Key = u64;
+    type Value = Rkyv<String>;
+}
+
+/// Reserved tree recording the rewrites of the running migration steps, keyed
+/// by step version and tree name.
+const REWRITES_TREE: &str = "__typed_sled_rewrites";
+
+fn rewrites(db: &SledDb) -> Result<Tree> {
+    Ok(db.inner_db().open_tree(REWRITES_TREE)?)
+}
+
+fn applied(db: &SledDb) -> Result<SledTree<MigrationSchema>> {
+    Ok(SledTree::new(
+        db.inner_db().open_tree(MigrationSchema::TREE_NAME.0)?,
+    ))
+}
+
+/// Returns the version of the last applied migration step.
+pub(crate) fn current_version(db: &SledDb) -> Result<Option<u64>> {
+    Ok(applied(db)?.last()?.map(|(version, _)| version))
+}
+
+/// Runs the steps of `migrations` newer than the applied version in order.
+pub(crate) fn run(db: &SledDb, migrations: &[Migration]) -> Result<usize> {
+    for pair in migrations.windows(2) {
+        if pair[0].version >= pair[1].version {
+            return Err(Error::InvalidMigrations(format!(
+                "migration {} is listed after migration {}",
+                pair[1].version, pair[0].version
+            )));
+        }
+    }
+
+    let applied = applied(db)?;
+    let current = applied.last()?.map(|(version, _)| version);
+    let latest = migrations.last().map(Migration::version);
+    if let Some(current) = current
+        && latest.is_none_or(|latest| current > latest)
+    {
+        return Err(Error::InvalidMigrations(format!(
+            "database is at migration {current}, newer than any known migration"
+        )));
+    }
+
+    let rewrites = rewrites(db)?;
+    let mut ran = 0;
+    for migration in migrations
+        .iter()
+        .filter(|migration| current.is_none_or(|current| migration.version > current))
+    {
+        let migrator = Migrator {
+            db,
+            version: migration.version,
+        };
+        (migration.run)(&migrator)?;
+        applied.insert(&migration.version, &Rkyv::new(migration.name.to_string()))?;
+        // The step no longer runs, so its rewrites need no record.
+        for marker in rewrites.scan_prefix(migration.version.to_be_bytes()).keys() {
+            rewrites.remove(marker?)?;
+        }
+        ran += 1;
+    }
+    Ok(ran)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::test_utils::*;
+
+    /// Stores the same values as [`TestSchema1`] under `u64` keys.
+    #[derive(Debug)]
+    struct WideKeySchema;
+
+    impl Schema for WideKeySchema {
+        const TREE_NAME: TreeName = TestSchema1::TREE_NAME;
+        type Key = u64;
+        type Value = TestValue;
+    }
+
+    /// Stores the same values as [`TestSchema1`] in another tree.
+    #[derive(Debug)]
+    struct RenamedSchema;
+
+    impl Schema for RenamedSchema {
+        const TREE_NAME: TreeName = TreeName("renamed");
+        type Key = u32;
+        type Value = TestValue;
+    }
+
+    fn insert_values(db: &SledDb) {
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        for value in [TestValue::alice(), TestValue::bob(), TestValue::charlie()] {
+            tree.insert(&value.id, &value).unwrap();
+        }
+    }
+
+    /// Returns a step that logs its version to `log`.
+    fn logged(version: u64, log: &Rc<RefCell<Vec<u64>>>) -> Migration {
+        let log = log.clone();
+        Migration::new(version, "logged", move |_| {
+            log.borrow_mut().push(version);
+            Ok(())
+        })
+    }
+
+    #[test]
+    fn test_pending_steps_run_once_in_order() {
+        let db = create_test_db().unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        assert_eq!(db.migration_version().unwrap(), None);
+
+        let first = [logged(1, &log), logged(2, &log)];
+        assert_eq!(db.run_migrations(&first).unwrap(), 2);
+        assert_eq!(db.run_migrations(&first).unwrap(), 0);
+        assert_eq!(db.migration_version().unwrap(), Some(2));
+
+        let second = [logged(1, &log), logged(2, &log), logged(5, &log)];
+        assert_eq!(db.run_migrations(&second).unwrap(), 1);
+        assert_eq!(*log.borrow(), vec![1, 2, 5]);
+        assert_eq!(db.migration_version().unwrap(), Some(5));
+    }
+
+    #[test]
+    fn test_failed_step_is_not_recorded() {
+        let db = create_test_db().unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let migrations = [
+            logged(1, &log),
+            Migration::new(2, "fails", |_| Err(Error::InvalidMigrations("boom".into()))),
+            logged(3, &log),
+        ];
+
+        assert!(db.run_migrations(&migrations).is_err());
+        assert_eq!(*log.borrow(), vec![1]);
+        assert_eq!(db.migration_version().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_invalid_migration_lists_are_rejected() {
+        let db = create_test_db().unwrap();
+        let log = Rc::new(RefCell::new(Vec::new()));
+
+        let unordered = [logged(2, &log), logged(1, &log)];
+        assert!(matches!(
+            db.run_migrations(&unordered),
+            Err(Error::InvalidMigrations(_))
+        ));
+        let duplicated = [logged(1, &log), logged(1, &log)];
+        assert!(db.run_migrations(&duplicated).is_err());
+        assert!(log.borrow().is_empty());
+
+        db.run_migrations(&[logged(3, &log)]).unwrap();
+        assert!(matches!(
+            db.run_migrations(&[logged(1, &log)]),
+            Err(Error::InvalidMigrations(_))
+        ));
+        assert!(db.run_migrations(&[]).is_err());
+    }
+
+    #[test]
+    fn test_rewrite_tree_in_place() {
+        let db = create_test_db().unwrap();
+        insert_values(&db);
+
+        let migrations = [Migration::new(1, "widen keys", |m| {
+            let written = m.rewrite_tree::<TestSchema1, WideKeySchema, _>(|key, value| {
+                (key != 2).then(|| (u64::from(key) << 32, value))
+            })?;
+            assert_eq!(written, 2);
+            Ok(())
+        })];
+        db.run_migrations(&migrations).unwrap();
+
+        assert!(matches!(
+            db.get_tree::<TestSchema1>(),
+            Err(Error::SchemaCollision { .. })
+        ));
+        let tree = db.get_tree::<WideKeySchema>().unwrap();
+        let entries: Vec<_> = tree.iter().map(|entry| entry.unwrap()).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, 1 << 32);
+        assert_test_values_eq(&TestValue::alice(), &entries[0].1);
+        assert_eq!(entries[1].0, 3 << 32);
+        assert_test_values_eq(&TestValue::charlie(), &entries[1].1);
+    }
+
+    #[test]
+    fn test_interrupted_rewrite_is_not_repeated() {
+        let db = create_test_db().unwrap();
+        insert_values(&db);
+        let widen = |m: &Migrator<'_>| {
+            m.rewrite_tree::<TestSchema1, WideKeySchema, _>(|key, value| {
+                Some((u64::from(key) << 32, value))
+            })
+        };
+
+        // The step fails after its rewrite was committed.
+        let interrupted = [Migration::new(1, "widen keys", move |m| {
+            assert_eq!(widen(m)?, 3);
+            Err(Error::InvalidMigrations("interrupted".into()))
+        })];
+        assert!(db.run_migrations(&interrupted).is_err());
+        assert_eq!(db.migration_version().unwrap(), None);
+
+        // Running it again skips the rewrite instead of decoding the new
+        // entries with the old schema.
+        let migrations = [Migration::new(1, "widen keys", move |m| {
+            assert_eq!(widen(m)?, 0);
+            Ok(())
+        })];
+        assert_eq!(db.run_migrations(&migrations).unwrap(), 1);
+
+        let tree = db.get_tree::<WideKeySchema>().unwrap();
+        let keys: Vec<_> = tree.iter().map(|entry| entry.unwrap().0).collect();
+        assert_eq!(keys, vec![1 << 32, 2 << 32, 3 << 32]);
+        assert!(rewrites(&db).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_rewrite_into_another_tree_and_drop() {
+        let db = create_test_db().unwrap();
+        insert_values(&db);
+
+        let migrations = [Migration::new(1, "rename", |m| {
+            m.rewrite_tree::<TestSchema1, RenamedSchema, _>(|key, value| Some((key, value)))?;
+            assert!(m.drop_tree::<TestSchema1>()?);
+            Ok(())
+        })];
+        db.run_migrations(&migrations).unwrap();
+
+        let renamed = db.get_tree::<RenamedSchema>().unwrap();
+        assert_eq!(renamed.iter().count(), 3);
+        assert_test_values_eq(&TestValue::bob(), &renamed.get(&2).unwrap().unwrap());
+
+        // The old tree and its registration are gone, so its name is free.
+        let reused = db.get_tree::<WideKeySchema>().unwrap();
+        assert!(reused.is_empty());
+    }
+}
@@ -0,0 +1,220 @@
+use std::{fmt, marker::PhantomData, str::FromStr};
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+
+use crate::{
+    KeyCodec, Schema,
+    error::{Error, Result},
+    tree::DecodedValue,
+};
+
+/// The order in which [`SledTree::page`](crate::SledTree::page) walks keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Ascending key order.
+    Forward,
+    /// Descending key order.
+    Backward,
+}
+
+/// Position after the last entry of a [`Page`], from which the next page
+/// continues.
+///
+/// A cursor is the encoded key of that entry. It round-trips through a
+/// URL-safe base64 string with [`ToString`] and [`FromStr`], so it can be
+/// handed to clients and parsed back when they ask for the next page.
+pub struct Cursor<S: Schema> {
+    key: Vec<u8>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Schema> Cursor<S> {
+    /// Creates a cursor positioned at `key`, so that the next page starts
+    /// right after it.
+    pub fn from_key(key: &S::Key) -> Result<Self> {
+        Ok(Self::new(key.encode_key()?))
+    }
+
+    pub(crate) fn new(key: Vec<u8>) -> Self {
+        Self {
+            key,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the key the cursor is positioned at.
+    pub fn key(&self) -> Result<S::Key> {
+        Ok(S::Key::decode_key(&self.key)?)
+    }
+
+    pub(crate) fn into_raw(self) -> Vec<u8> {
+        self.key
+    }
+}
+
+impl<S: Schema> Clone for Cursor<S> {
+    fn clone(&self) -> Self {
+        Self::new(self.key.clone())
+    }
+}
+
+impl<S: Schema> PartialEq for Cursor<S> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<S: Schema> Eq for Cursor<S> {}
+
+impl<S: Schema> fmt::Debug for Cursor<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Cursor").field(&self.to_string()).finish()
+    }
+}
+
+impl<S: Schema> fmt::Display for Cursor<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&URL_SAFE_NO_PAD.encode(&self.key))
+    }
+}
+
+impl<S: Schema> FromStr for Cursor<S> {
+    type Err = Error;
+
+    /// Parses a cursor, failing if it is not base64 or does not hold a key
+    /// of `S`.
+    fn from_str(s: &str) -> Result<Self> {
+        let key = URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|e| Error::InvalidCursor(e.to_string()))?;
+        S::Key::decode_key(&key).map_err(|e| Error::InvalidCursor(e.to_string()))?;
+        Ok(Self::new(key))
+    }
+}
+
+/// One page of entries returned by [`SledTree::page`](crate::SledTree::page).
+pub struct Page<S: Schema> {
+    /// The entries of the page, in the requested direction.
+    pub items: Vec<(S::Key, DecodedValue<S>)>,
+    /// The cursor to request the next page with, or `None` if this is the
+    /// last page.
+    pub next: Option<Cursor<S>>,
+}
+
+impl<S: Schema> fmt::Debug for Page<S>
+where
+    S::Key: fmt::Debug,
+    DecodedValue<S>: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Page")
+            .field("items", &self.items)
+            .field("next", &self.next)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SledTree, test_utils::*};
+
+    fn populated_tree() -> SledTree<TestSchema1> {
+        let tree = create_temp_tree::<TestSchema1>().unwrap();
+        for id in 1..=5 {
+            tree.insert(&id, &TestValue::new_with_name(id)).unwrap();
+        }
+        tree
+    }
+
+    fn page_keys(page: &Page<TestSchema1>) -> Vec<u32> {
+        page.items.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[test]
+    fn test_pages_forward_until_exhausted() {
+        let tree = populated_tree();
+
+        let first = tree.page(None, 2, Direction::Forward).unwrap();
+        assert_eq!(page_keys(&first), vec![1, 2]);
+        assert_test_values_eq(&TestValue::new_with_name(1), &first.items[0].1);
+
+        let second = tree.page(first.next, 2, Direction::Forward).unwrap();
+        assert_eq!(page_keys(&second), vec![3, 4]);
+
+        let last = tree.page(second.next, 2, Direction::Forward).unwrap();
+        assert_eq!(page_keys(&last), vec![5]);
+        assert!(last.next.is_none());
+    }
+
+    #[test]
+    fn test_pages_backward() {
+        let tree = populated_tree();
+
+        let first = tree.page(None, 3, Direction::Backward).unwrap();
+        assert_eq!(page_keys(&first), vec![5, 4, 3]);
+
+        let last = tree.page(first.next, 3, Direction::Backward).unwrap();
+        assert_eq!(page_keys(&last), vec![2, 1]);
+        assert!(last.next.is_none());
+    }
+
+    #[test]
+    fn test_full_last_page_has_no_cursor() {
+        let tree = populated_tree();
+
+        let page = tree.page(None, 5, Direction::Forward).unwrap();
+        assert_eq!(page.items.len(), 5);
+        assert!(page.next.is_none());
+    }
+
+    #[test]
+    fn test_empty_page_limit_is_rejected() {
+        let tree = populated_tree();
+        let cursor = tree.page(None, 2, Direction::Forward).unwrap().next;
+
+        for cursor in [None, cursor] {
+            assert!(matches!(
+                tree.page(cursor, 0, Direction::Forward),
+                Err(Error::InvalidCursor(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_cursor_round_trips_through_string() {
+        let tree = populated_tree();
+
+        let next = tree
+            .page(None, 2, Direction::Forward)
+            .unwrap()
+            .next
+            .unwrap();
+        let encoded = next.to_string();
+        let parsed: Cursor<TestSchema1> = encoded.parse().unwrap();
+        assert_eq!(parsed, next);
+        assert_eq!(parsed.key().unwrap(), 2);
+
+        let page = tree.page(Some(parsed), 2, Direction::Forward).unwrap();
+        assert_eq!(page_keys(&page), vec![3, 4]);
+
+        // Entries inserted behind the cursor do not shift later pages.
+        tree.insert(&0, &TestValue::new_with_name(0)).unwrap();
+        let cursor = Cursor::from_key(&2).unwrap();
+        let page = tree.page(Some(cursor), 2, Direction::Forward).unwrap();
+        assert_eq!(page_keys(&page), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_invalid_cursor_is_rejected() {
+        assert!(matches!(
+            "not base64!".parse::<Cursor<TestSchema1>>(),
+            Err(Error::InvalidCursor(_))
+        ));
+        // Valid base64, but too short for a `u32` key.
+        assert!(matches!(
+            "AQ".parse::<Cursor<TestSchema1>>(),
+            Err(Error::InvalidCursor(_))
+        ));
+    }
+}
@@ -0,0 +1,379 @@
+use std::any::type_name;
+
+use rkyv::{Archive, Deserialize, Serialize};
+use sled::{Db, IVec, Tree};
+
+use crate::{
+    KeyCodec, Rkyv, Schema, SledTree, TreeName, ValueCodec,
+    error::{Error, Result},
+    index::Index,
+};
+
+/// Prefix of the tree names reserved for typed-sled's own metadata.
+pub(crate) const RESERVED_PREFIX: &str = "__typed_sled";
+
+/// Returns the declared identifier `id` of `T`, or its full type name.
+fn stable_id<T: ?Sized>(id: Option<&'static str>) -> &'static str {
+    id.unwrap_or_else(type_name::<T>)
+}
+
+/// Returns the identifier schema `S` is registered under.
+pub(crate) fn schema_id<S: Schema>() -> &'static str {
+    stable_id::<S>(S::ID)
+}
+
+/// What the schema registry records about the schema of a tree.
+///
+/// Types are recorded by their declared identifiers, or by their full type
+/// names if they declare none.
+#[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SchemaRecord {
+    schema: String,
+    key_codec: String,
+    value_codec: String,
+    version: u32,
+}
+
+impl SchemaRecord {
+    /// Describes schema `S`.
+    pub(crate) fn of<S: Schema>() -> Self {
+        Self {
+            schema: schema_id::<S>().to_string(),
+            key_codec: stable_id::<S::Key>(<S::Key as KeyCodec<S>>::CODEC_ID).to_string(),
+            value_codec: stable_id::<S::Value>(<S::Value as ValueCodec<S>>::CODEC_ID).to_string(),
+            version: S::VERSION,
+        }
+    }
+
+    /// Describes index `I` over schema `S`, whose entries map index keys to
+    /// primary keys.
+    pub(crate) fn of_index<S: Schema, I: Index<S>>() -> Self {
+        Self {
+            schema: stable_id::<I>(I::ID).to_string(),
+            key_codec: stable_id::<I::Key>(<I::Key as KeyCodec<S>>::CODEC_ID).to_string(),
+            value_codec: stable_id::<S::Key>(<S::Key as KeyCodec<S>>::CODEC_ID).to_string(),
+            // Unique indexes use another entry layout.
+            version: u32::from(I::UNIQUE),
+        }
+    }
+
+    /// Checks that the schema `requested` of `tree`, described by `self`,
+    /// matches the `stored` record.
+    fn check(
+        &self,
+        tree: &'static str,
+        requested: &'static str,
+        stored: &SchemaRecord,
+    ) -> Result<()> {
+        if stored.schema != self.schema {
+            return Err(Error::SchemaCollision {
+                tree,
+                registered: stored.schema.clone(),
+                requested,
+            });
+        }
+        let fields = [
+            ("key codec", &stored.key_codec, &self.key_codec),
+            ("value codec", &stored.value_codec, &self.value_codec),
+        ];
+        for (field, stored, declared) in fields {
+            if stored != declared {
+                return Err(Error::SchemaMismatch {
+                    tree,
+                    field,
+                    stored: stored.clone(),
+                    declared: declared.clone(),
+                });
+            }
+        }
+        if stored.version != self.version {
+            return Err(Error::SchemaMismatch {
+                tree,
+                field: "version",
+                stored: stored.version.to_string(),
+                declared: self.version.to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Reserved tree mapping tree names to the schema registered for them.
+#[derive(Debug)]
+struct RegistrySchema;
+
+impl Schema for RegistrySchema {
+    const TREE_NAME: TreeName = TreeName("__typed_sled_schemas");
+    type Key = String;
+    type Value = Rkyv<SchemaRecord>;
+}
+
+fn registry(db: &Db) -> Result<SledTree<RegistrySchema>> {
+    Ok(SledTree::new(db.open_tree(RegistrySchema::TREE_NAME.0)?))
+}
+
+/// Checks schema `S` against the registration of its tree, registering it
+/// if the tree has none yet.
+pub(crate) fn check_or_register<S: Schema>(db: &Db) -> Result<()> {
+    let declared = SchemaRecord::of::<S>();
+    check_or_register_record(db, S::TREE_NAME.0, schema_id::<S>(), declared).map(drop)
+}
+
+/// Checks index `I` against the registration of its tree, returning `false`
+/// if the tree has none yet.
+pub(crate) fn check_index<S: Schema, I: Index<S>>(db: &Db) -> Result<bool> {
+    let declared = SchemaRecord::of_index::<S, I>();
+    match registry(db)?.get(I::TREE_NAME.0)? {
+        Some(stored) => declared
+            .check(I::TREE_NAME.0, stable_id::<I>(I::ID), &stored)
+            .map(|()| true),
+        None => Ok(false),
+    }
+}
+
+/// Checks index `I` against the registration of its tree, registering it
+/// if the tree has none yet.
+pub(crate) fn check_or_register_index<S: Schema, I: Index<S>>(db: &Db) -> Result<()> {
+    let declared = SchemaRecord::of_index::<S, I>();
+    check_or_register_record(db, I::TREE_NAME.0, stable_id::<I>(I::ID), declared).map(drop)
+}
+
+/// Checks `declared` against the registration of `tree`, registering it if
+/// the tree has none yet. Returns `true` if it was registered now.
+fn check_or_register_record(
+    db: &Db,
+    tree: &'static str,
+    requested: &'static str,
+    declared: SchemaRecord,
+) -> Result<bool> {
+    let registry = registry(db)?;
+    let name = tree.to_string();
+    loop {
+        if let Some(stored) = registry.get(&name)? {
+            return declared.check(tree, requested, &stored).map(|()| false);
+        }
+        let record = Some(Rkyv::new(declared.clone()));
+        match registry.compare_and_swap(name.clone(), None, record)? {
+            Ok(()) => return Ok(true),
+            // Registered concurrently, check against that registration.
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Returns the registry tree with the raw entry registering schema `S`, for
+/// registering it in a transaction with other writes.
+pub(crate) fn registration<S: Schema>(db: &Db) -> Result<(Tree, Vec<u8>, Vec<u8>)> {
+    let key = KeyCodec::<RegistrySchema>::encode_key(&S::TREE_NAME.0.to_string())?;
+    let record: <RegistrySchema as Schema>::Value = Rkyv::new(SchemaRecord::of::<S>());
+    let record = ValueCodec::<RegistrySchema>::encode_value(&record)?;
+    Ok((registry(db)?.inner, key, record))
+}
+
+/// Registers schema `S` for its tree, replacing any previous registration.
+pub(crate) fn register<S: Schema>(db: &Db) -> Result<()> {
+    registry(db)?.insert(
+        &S::TREE_NAME.0.to_string(),
+        &Rkyv::new(SchemaRecord::of::<S>()),
+    )
+}
+
+/// Removes the registration of `tree` and the indexes attached to it.
+pub(crate) fn unregister(db: &Db, tree: &str) -> Result<()> {
+    let attachments = attachments(db)?;
+    for entry in attachments.scan_prefix(attachment_prefix(tree)).keys() {
+        attachments.remove(entry?)?;
+    }
+    registry(db)?.remove(&tree.to_string())
+}
+
+/// Reserved tree recording the indexes attached to each tree, keyed by tree
+/// name and index tree name.
+const INDEXES_TREE: &str = "__typed_sled_indexes";
+
+fn attachments(db: &Db) -> Result<Tree> {
+    Ok(db.open_tree(INDEXES_TREE)?)
+}
+
+fn attachment_prefix(tree: &str) -> Vec<u8> {
+    let mut prefix = tree.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// Records that index `I` is attached to the tree of `S`.
+pub(crate) fn attach_index<S: Schema, I: Index<S>>(db: &Db) -> Result<()> {
+    let mut entry = attachment_prefix(S::TREE_NAME.0);
+    entry.extend_from_slice(I::TREE_NAME.0.as_bytes());
+    attachments(db)?.insert(entry, &[])?;
+    Ok(())
+}
+
+/// Returns the tree names of the indexes attached to `tree`.
+pub(crate) fn indexes_of(db: &Db, tree: &str) -> Result<Vec<String>> {
+    let prefix = attachment_prefix(tree);
+    attachments(db)?
+        .scan_prefix(&prefix)
+        .keys()
+        .map(|entry| Ok(String::from_utf8_lossy(&entry?[prefix.len()..]).into_owned()))
+        .collect()
+}
+
+/// The raw entry attaching an index to a tree, with the raw key of the
+/// index's registration.
+pub(crate) type Attachment = (IVec, Vec<u8>);
+
+/// Returns the attachment tree with the attachments of the indexes of
+/// `tree`, for detaching them in a transaction with other writes.
+pub(crate) fn attached_indexes(db: &Db, tree: &str) -> Result<(Tree, Vec<Attachment>)> {
+    let attachments = attachments(db)?;
+    let prefix = attachment_prefix(tree);
+    let entries = attachments
+        .scan_prefix(&prefix)
+        .keys()
+        .map(|entry| {
+            let entry = entry?;
+            let index = String::from_utf8_lossy(&entry[prefix.len()..]).into_owned();
+            let record = KeyCodec::<RegistrySchema>::encode_key(&index)?;
+            Ok((entry, record))
+        })
+        .collect::<Result<_>>()?;
+    Ok((attachments, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn test_changed_fields_are_reported() {
+        let declared = SchemaRecord::of::<TestSchema1>();
+        let cases = [
+            (
+                SchemaRecord {
+                    key_codec: "u64".to_string(),
+                    ..declared.clone()
+                },
+                "key codec",
+                "u64",
+                "u32",
+            ),
+            (
+                SchemaRecord {
+                    value_codec: "OldValue".to_string(),
+                    ..declared.clone()
+                },
+                "value codec",
+                "OldValue",
+                type_name::<TestValue>(),
+            ),
+            (
+                SchemaRecord {
+                    version: 3,
+                    ..declared.clone()
+                },
+                "version",
+                "3",
+                "0",
+            ),
+        ];
+
+        for (stored, expected_field, expected_stored, expected_declared) in cases {
+            match declared.check(TestSchema1::TREE_NAME.0, "TestSchema1", &stored) {
+                Err(Error::SchemaMismatch {
+                    tree,
+                    field,
+                    stored,
+                    declared,
+                }) => {
+                    assert_eq!(tree, TestSchema1::TREE_NAME.0);
+                    assert_eq!(field, expected_field);
+                    assert_eq!(stored, expected_stored);
+                    assert_eq!(declared, expected_declared);
+                }
+                other => panic!("expected schema mismatch, got {other:?}"),
+            }
+        }
+        assert!(
+            declared
+                .check(TestSchema1::TREE_NAME.0, "TestSchema1", &declared)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_first_open_registers_schema() {
+        let sled_db = create_temp_sled_db();
+        check_or_register::<TestSchema1>(&sled_db).unwrap();
+        check_or_register::<TestSchema1>(&sled_db).unwrap();
+
+        let stored = registry(&sled_db)
+            .unwrap()
+            .get(&TestSchema1::TREE_NAME.0.to_string())
+            .unwrap();
+        assert_eq!(stored, Some(SchemaRecord::of::<TestSchema1>()));
+    }
+
+    mod billing {
+        use super::*;
+
+        #[derive(Debug)]
+        pub(super) struct Accounts;
+
+        impl Schema for Accounts {
+            const TREE_NAME: TreeName = TreeName("accounts");
+            type Key = u32;
+            type Value = TestValue;
+        }
+    }
+
+    mod auth {
+        use super::*;
+
+        #[derive(Debug)]
+        pub(super) struct Accounts;
+
+        impl Schema for Accounts {
+            const TREE_NAME: TreeName = TreeName("accounts");
+            type Key = u32;
+            type Value = TestValue;
+        }
+    }
+
+    #[test]
+    fn test_schemas_sharing_a_short_name_collide() {
+        let sled_db = create_temp_sled_db();
+        check_or_register::<billing::Accounts>(&sled_db).unwrap();
+
+        match check_or_register::<auth::Accounts>(&sled_db) {
+            Err(Error::SchemaCollision {
+                registered,
+                requested,
+                ..
+            }) => {
+                assert_eq!(registered, type_name::<billing::Accounts>());
+                assert_eq!(requested, type_name::<auth::Accounts>());
+            }
+            other => panic!("expected schema collision, got {other:?}"),
+        }
+    }
+
+    #[derive(Debug)]
+    struct AccountSchema;
+
+    impl Schema for AccountSchema {
+        const TREE_NAME: TreeName = TreeName("accounts");
+        const ID: Option<&'static str> = Some("accounts-v1");
+        type Key = u32;
+        type Value = TestValue;
+    }
+
+    #[test]
+    fn test_declared_ids_are_recorded() {
+        let record = SchemaRecord::of::<AccountSchema>();
+        assert_eq!(record.schema, "accounts-v1");
+        assert_eq!(record.key_codec, "u32");
+        assert_eq!(record.value_codec, type_name::<TestValue>());
+    }
+}
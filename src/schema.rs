@@ -28,4 +28,22 @@ pub trait Schema: Debug + Send + Sync + Sized {
     type Key: KeyCodec<Self>;
     /// The value type for this schema.
     type Value: ValueCodec<Self>;
+
+    /// Stable identifier of the schema, recorded in the schema registry to
+    /// tell apart schemas sharing a tree name.
+    ///
+    /// Defaults to the schema's full type name, module path included, so
+    /// types of the same name in different modules are told apart. Declare
+    /// an identifier to keep the registration valid when the type moves or
+    /// is renamed.
+    const ID: Option<&'static str> = None;
+
+    /// User-declared version of the tree layout, recorded in the schema
+    /// registry when the tree is first opened.
+    ///
+    /// Opening a tree whose recorded version differs fails with
+    /// [`Error::SchemaMismatch`](crate::error::Error::SchemaMismatch) until the
+    /// tree is migrated or re-registered with
+    /// [`SledDb::register_schema`](crate::SledDb::register_schema).
+    const VERSION: u32 = 0;
 }
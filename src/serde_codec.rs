@@ -0,0 +1,389 @@
+use std::ops::Deref;
+
+use serde::{Serialize, de::DeserializeOwned};
+use sled::IVec;
+
+#[cfg(feature = "bincode")]
+use crate::KeyCodec;
+use crate::{CodecError, CodecResult, Schema, ValueCodec};
+
+/// Implements the common wrapper boilerplate for a serde adapter.
+macro_rules! serde_adapter {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name<T>(pub T);
+
+        impl<T> $name<T> {
+            /// Returns the wrapped value.
+            pub fn into_inner(self) -> T {
+                self.0
+            }
+        }
+
+        impl<T> From<T> for $name<T> {
+            fn from(value: T) -> Self {
+                Self(value)
+            }
+        }
+
+        impl<T> Deref for $name<T> {
+            type Target = T;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+    };
+}
+
+#[cfg(feature = "serde-json")]
+serde_adapter!(
+    /// JSON value codec for any `serde` type. Values decode into an owned `T`.
+    Json
+);
+
+#[cfg(feature = "serde-json")]
+impl<S, T> ValueCodec<S> for Json<T>
+where
+    S: Schema,
+    T: Serialize + DeserializeOwned,
+{
+    type Decoded = T;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        serde_json::to_vec(&self.0).map_err(|e| CodecError::SerializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        })
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        serde_json::from_slice(&buf).map_err(|e| CodecError::DeserializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        })
+    }
+}
+
+#[cfg(feature = "bincode")]
+serde_adapter!(
+    /// Bincode codec for any `serde` type, usable as a value or, for
+    /// [`BincodeOrdered`] types, as a key.
+    ///
+    /// Integers are written big-endian with a fixed width, so as a key the
+    /// byte order matches the field-by-field order of structs and tuples
+    /// made of unsigned integers, `bool`s and fixed-size arrays. Signed
+    /// integers, strings and collections do not keep their order (strings
+    /// are length-prefixed); use the built-in [`KeyCodec`] impls for those.
+    Bincode
+);
+
+/// Marker for types whose [`Bincode`] encoding sorts like the values it
+/// encodes, which makes `Bincode<T>` a [`KeyCodec`].
+///
+/// Implemented for unsigned integers, `bool`, `()` and options, arrays and
+/// tuples of such types. Implement it for a struct whose fields are all
+/// `BincodeOrdered`, declared in the order it should sort by, and whose
+/// [`Ord`] compares them in that order.
+#[cfg(feature = "bincode")]
+pub trait BincodeOrdered: Serialize + DeserializeOwned {}
+
+#[cfg(feature = "bincode")]
+macro_rules! bincode_ordered {
+    ($($ty:ty),*) => {
+        $(impl BincodeOrdered for $ty {})*
+    };
+}
+
+#[cfg(feature = "bincode")]
+bincode_ordered!((), bool, u8, u16, u32, u64, u128);
+
+#[cfg(feature = "bincode")]
+impl<T: BincodeOrdered> BincodeOrdered for Option<T> {}
+
+#[cfg(feature = "bincode")]
+impl<T: BincodeOrdered, const N: usize> BincodeOrdered for [T; N] where
+    [T; N]: Serialize + DeserializeOwned
+{
+}
+
+#[cfg(feature = "bincode")]
+macro_rules! bincode_ordered_tuple {
+    ($($name:ident),+) => {
+        impl<$($name: BincodeOrdered),+> BincodeOrdered for ($($name,)+) {}
+    };
+}
+
+#[cfg(feature = "bincode")]
+bincode_ordered_tuple!(A0);
+#[cfg(feature = "bincode")]
+bincode_ordered_tuple!(A0, A1);
+#[cfg(feature = "bincode")]
+bincode_ordered_tuple!(A0, A1, A2);
+#[cfg(feature = "bincode")]
+bincode_ordered_tuple!(A0, A1, A2, A3);
+#[cfg(feature = "bincode")]
+bincode_ordered_tuple!(A0, A1, A2, A3, A4);
+#[cfg(feature = "bincode")]
+bincode_ordered_tuple!(A0, A1, A2, A3, A4, A5);
+
+#[cfg(feature = "bincode")]
+const BINCODE_CONFIG: bincode::config::Configuration<
+    bincode::config::BigEndian,
+    bincode::config::Fixint,
+> = bincode::config::standard()
+    .with_big_endian()
+    .with_fixed_int_encoding();
+
+/// Serializes `value` with [`BINCODE_CONFIG`], reporting failures against schema `S`.
+#[cfg(feature = "bincode")]
+fn bincode_encode<S: Schema, T: Serialize>(value: &T) -> CodecResult<Vec<u8>> {
+    bincode::serde::encode_to_vec(value, BINCODE_CONFIG).map_err(|e| {
+        CodecError::SerializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        }
+    })
+}
+
+/// Deserializes a value written by [`bincode_encode`], returning it along
+/// with the number of bytes read.
+#[cfg(feature = "bincode")]
+fn bincode_decode<S: Schema, T: DeserializeOwned>(buf: &[u8]) -> CodecResult<(T, usize)> {
+    bincode::serde::decode_from_slice(buf, BINCODE_CONFIG).map_err(|e| {
+        CodecError::DeserializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        }
+    })
+}
+
+#[cfg(feature = "bincode")]
+impl<S, T> ValueCodec<S> for Bincode<T>
+where
+    S: Schema,
+    T: Serialize + DeserializeOwned,
+{
+    type Decoded = T;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        bincode_encode::<S, T>(&self.0)
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        bincode_decode::<S, T>(&buf).map(|(value, _)| value)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl<S, T> KeyCodec<S> for Bincode<T>
+where
+    S: Schema,
+    T: BincodeOrdered,
+{
+    fn encode_key(&self) -> CodecResult<Vec<u8>> {
+        bincode_encode::<S, T>(&self.0)
+    }
+
+    fn decode_key(buf: &[u8]) -> CodecResult<Self> {
+        let (key, len) = bincode_decode::<S, T>(buf)?;
+        if len != buf.len() {
+            return Err(CodecError::InvalidKeyLength {
+                schema: S::TREE_NAME.0,
+                expected: len,
+                actual: buf.len(),
+            });
+        }
+        Ok(Self(key))
+    }
+}
+
+#[cfg(feature = "postcard")]
+serde_adapter!(
+    /// Postcard value codec for any `serde` type. Values decode into an owned `T`.
+    ///
+    /// Postcard uses variable-length integers, so it has no key adapter.
+    Postcard
+);
+
+#[cfg(feature = "postcard")]
+impl<S, T> ValueCodec<S> for Postcard<T>
+where
+    S: Schema,
+    T: Serialize + DeserializeOwned,
+{
+    type Decoded = T;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        postcard::to_allocvec(&self.0).map_err(|e| CodecError::SerializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        })
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        postcard::from_bytes(&buf).map_err(|e| CodecError::DeserializationFailed {
+            schema: S::TREE_NAME.0,
+            source: e.into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::*;
+    use crate::{KeyCodec, SledTree, TreeName, error::Error, test_utils::*};
+
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct Account {
+        owner: String,
+        balance: u64,
+        tags: Vec<String>,
+    }
+
+    fn account(balance: u64) -> Account {
+        Account {
+            owner: "alice".to_string(),
+            balance,
+            tags: vec!["a".to_string(), "b".to_string()],
+        }
+    }
+
+    /// Inserts, reads back and corrupts a value to exercise a value adapter.
+    fn check_value_adapter<S>(tree: SledTree<S>)
+    where
+        S: Schema<Key = u32>,
+        S::Value: From<Account> + ValueCodec<S, Decoded = Account>,
+    {
+        tree.insert(&1, &account(10).into()).unwrap();
+        tree.insert(&2, &account(20).into()).unwrap();
+
+        assert_eq!(tree.get(&1).unwrap(), Some(account(10)));
+        let items: Vec<_> = tree.iter().map(|item| item.unwrap().1).collect();
+        assert_eq!(items, vec![account(10), account(20)]);
+
+        let key = <u32 as KeyCodec<S>>::encode_key(&3).unwrap();
+        tree.inner.insert(key, vec![0xff_u8]).unwrap();
+        match tree.get(&3) {
+            Err(Error::CodecError(CodecError::DeserializationFailed { schema, .. })) => {
+                assert_eq!(schema, S::TREE_NAME.0)
+            }
+            other => panic!("expected deserialization error, got {other:?}"),
+        }
+    }
+
+    #[cfg(feature = "serde-json")]
+    #[test]
+    fn test_json_value_roundtrip() {
+        #[derive(Debug)]
+        struct JsonSchema;
+
+        impl Schema for JsonSchema {
+            const TREE_NAME: TreeName = TreeName("json");
+            type Key = u32;
+            type Value = Json<Account>;
+        }
+
+        check_value_adapter(create_temp_tree::<JsonSchema>().unwrap());
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard_value_roundtrip() {
+        #[derive(Debug)]
+        struct PostcardSchema;
+
+        impl Schema for PostcardSchema {
+            const TREE_NAME: TreeName = TreeName("postcard");
+            type Key = u32;
+            type Value = Postcard<Account>;
+        }
+
+        check_value_adapter(create_temp_tree::<PostcardSchema>().unwrap());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_value_roundtrip() {
+        #[derive(Debug)]
+        struct BincodeSchema;
+
+        impl Schema for BincodeSchema {
+            const TREE_NAME: TreeName = TreeName("bincode");
+            type Key = u32;
+            type Value = Bincode<Account>;
+        }
+
+        check_value_adapter(create_temp_tree::<BincodeSchema>().unwrap());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_key_preserves_unsigned_order() {
+        #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+        struct HeightKey {
+            chain: u16,
+            height: u64,
+        }
+
+        impl BincodeOrdered for HeightKey {}
+
+        #[derive(Debug)]
+        struct BincodeKeySchema;
+
+        impl Schema for BincodeKeySchema {
+            const TREE_NAME: TreeName = TreeName("bincode_keys");
+            type Key = Bincode<HeightKey>;
+            type Value = TestValue;
+        }
+
+        let tree = create_temp_tree::<BincodeKeySchema>().unwrap();
+        let mut keys = vec![(2, 1), (1, 300), (1, 2), (256, 0), (1, u64::MAX)]
+            .into_iter()
+            .map(|(chain, height)| Bincode(HeightKey { chain, height }))
+            .collect::<Vec<_>>();
+        for key in &keys {
+            tree.insert(key, &TestValue::alice()).unwrap();
+        }
+
+        let stored: Vec<_> = tree.iter().map(|item| item.unwrap().0).collect();
+        keys.sort();
+        assert_eq!(stored, keys);
+
+        let err =
+            <Bincode<HeightKey> as KeyCodec<BincodeKeySchema>>::decode_key(&[0; 9]).unwrap_err();
+        assert!(matches!(err, CodecError::DeserializationFailed { .. }));
+        let err =
+            <Bincode<HeightKey> as KeyCodec<BincodeKeySchema>>::decode_key(&[0; 13]).unwrap_err();
+        assert!(matches!(
+            err,
+            CodecError::InvalidKeyLength {
+                expected: 10,
+                actual: 13,
+                ..
+            }
+        ));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_ordered_keys_encode_in_order() {
+        type Key = (Option<u8>, [u16; 2], bool);
+
+        let keys: [Key; 5] = [
+            (None, [7, 7], true),
+            (Some(0), [0, 9], false),
+            (Some(0), [1, 0], false),
+            (Some(0), [1, 0], true),
+            (Some(200), [0, 0], false),
+        ];
+        let encoded: Vec<_> = keys
+            .into_iter()
+            .map(|key| KeyCodec::<TestSchema1>::encode_key(&Bincode(key)).unwrap())
+            .collect();
+        assert!(encoded.is_sorted());
+    }
+}
@@ -91,7 +91,7 @@ impl Schema for TestSchema3 {
 
 impl<S> ValueCodec<S> for TestValue
 where
-    S: Schema<Key = u32, Value = TestValue>,
+    S: Schema<Value = TestValue>,
 {
     type Decoded = Self;
 
@@ -143,3 +143,32 @@ pub(crate) fn assert_test_values_eq(expected: &TestValue, actual: &TestValue) {
         expected.id, expected.name, actual.id, actual.name
     );
 }
+
+/// Drives `future` to completion on the current thread.
+#[cfg(feature = "async")]
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::{
+        pin::pin,
+        sync::Arc,
+        task::{Context, Poll, Wake, Waker},
+        thread::{self, Thread},
+    };
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
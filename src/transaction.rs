@@ -1,7 +1,9 @@
+#[cfg(feature = "async")]
+use std::future::Future;
 use std::time::Duration;
 
 use sled::{
-    Transactional,
+    Transactional, Tree,
     transaction::{ConflictableTransactionResult, TransactionError, TransactionResult},
 };
 
@@ -131,6 +133,29 @@ impl Backoff for ConstantBackoff {
     }
 }
 
+/// Async timer used by [`SledTransactional::transaction_with_retry_async`]
+/// to wait between attempts.
+///
+/// Implemented for every `Fn(Duration) -> impl Future<Output = ()>`, so a
+/// runtime's sleep function can be passed directly, e.g.
+/// `&tokio::time::sleep`.
+#[cfg(feature = "async")]
+pub trait AsyncSleeper: Send + Sync {
+    /// Returns a future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send;
+}
+
+#[cfg(feature = "async")]
+impl<F, Fut> AsyncSleeper for F
+where
+    F: Fn(Duration) -> Fut + Send + Sync,
+    Fut: Future<Output = ()> + Send,
+{
+    fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send {
+        self(duration)
+    }
+}
+
 /// Trait for performing transactions on typed sled trees.
 pub trait SledTransactional {
     /// The transactional view type.
@@ -175,6 +200,48 @@ pub trait SledTransactional {
             }
         }
     }
+
+    /// Like [`SledTransactional::transaction_with_retry`], but waits between
+    /// attempts with `sleeper` instead of blocking the thread.
+    ///
+    /// Each attempt still runs the transaction synchronously.
+    #[cfg(feature = "async")]
+    fn transaction_with_retry_async<F, R, E>(
+        &self,
+        backoff: &dyn Backoff,
+        sleeper: &impl AsyncSleeper,
+        max_retries: usize,
+        func: F,
+    ) -> impl Future<Output = TransactionResult<R, E>> + Send
+    where
+        Self: Sync,
+        F: Fn(Self::View) -> ConflictableTransactionResult<R, E> + Send,
+    {
+        async move {
+            let mut attempts = 0;
+            let mut delay_ms = backoff.base_delay_ms();
+
+            loop {
+                match self.transaction(&func) {
+                    Ok(result) => return Ok(result),
+                    Err(TransactionError::Abort(err)) => {
+                        // User explicitly aborted, don't retry
+                        return Err(TransactionError::Abort(err));
+                    }
+                    Err(TransactionError::Storage(storage_err)) => {
+                        if attempts >= max_retries {
+                            return Err(TransactionError::Storage(storage_err));
+                        }
+                    }
+                }
+
+                // Only retry on storage conflicts (like write conflicts)
+                sleeper.sleep(Duration::from_millis(delay_ms)).await;
+                delay_ms = backoff.next_delay_ms(delay_ms);
+                attempts += 1;
+            }
+        }
+    }
 }
 
 /* Definition of implementations like this for various tuple arities
@@ -198,7 +265,7 @@ impl<S1: Schema> SledTransactional for (&SledTree<S1>,) {
 /// similar interface to what [sled provides]
 /// (<https://docs.rs/sled/latest/sled/struct.Tree.html#method.transaction>).
 macro_rules! impl_sled_transactional {
-    ($(($idx:tt, $schema:ident, $var:ident)),+) => {
+    ($(($idx:tt, $schema:ident)),+) => {
         /// Impl for owned `SledTree`
         impl<$($schema: Schema),+> SledTransactional for ($(SledTree<$schema>),+,) {
             type View = ($(SledTransactionalTree<$schema>),+,);
@@ -207,9 +274,19 @@ macro_rules! impl_sled_transactional {
             where
                 F: Fn(Self::View) -> ConflictableTransactionResult<R, E>,
             {
-                ($(&self.$idx.inner),+,).transaction(|($($var),+,)| {
-                    func(($(SledTransactionalTree::<$schema>::new($var.clone())),+,))
-                })
+                // Each tree is followed by the trees of its attached indexes.
+                let attached = ($(self.$idx.indexes.read()),+,);
+                let mut trees: Vec<Tree> = Vec::new();
+                $(
+                    trees.push(self.$idx.inner.clone());
+                    trees.extend(attached.$idx.trees().cloned());
+                )+
+                let result = trees.as_slice().transaction(|views| {
+                    let mut views = views.iter();
+                    func(($(SledTransactionalTree::<$schema>::for_tree(&mut views, &self.$idx, &attached.$idx)),+,))
+                })?;
+                $(self.$idx.flusher.after_transaction(&self.$idx.inner);)+
+                Ok(result)
             }
         }
 
@@ -221,33 +298,30 @@ macro_rules! impl_sled_transactional {
             where
                 F: Fn(Self::View) -> ConflictableTransactionResult<R, E>,
             {
-                ($(&self.$idx.inner),+,).transaction(|($($var),+,)| {
-                    func(($(SledTransactionalTree::<$schema>::new($var.clone())),+,))
-                })
+                // Each tree is followed by the trees of its attached indexes.
+                let attached = ($(self.$idx.indexes.read()),+,);
+                let mut trees: Vec<Tree> = Vec::new();
+                $(
+                    trees.push(self.$idx.inner.clone());
+                    trees.extend(attached.$idx.trees().cloned());
+                )+
+                let result = trees.as_slice().transaction(|views| {
+                    let mut views = views.iter();
+                    func(($(SledTransactionalTree::<$schema>::for_tree(&mut views, &self.$idx, &attached.$idx)),+,))
+                })?;
+                $(self.$idx.flusher.after_transaction(&self.$idx.inner);)+
+                Ok(result)
             }
         }
     };
 }
 
-impl_sled_transactional!((0, S0, t0));
-impl_sled_transactional!((0, S0, t0), (1, S1, t1));
-impl_sled_transactional!((0, S0, t0), (1, S1, t1), (2, S2, t2));
-impl_sled_transactional!((0, S0, t0), (1, S1, t1), (2, S2, t2), (3, S3, t3));
-impl_sled_transactional!(
-    (0, S0, t0),
-    (1, S1, t1),
-    (2, S2, t2),
-    (3, S3, t3),
-    (4, S4, t4)
-);
-impl_sled_transactional!(
-    (0, S0, t0),
-    (1, S1, t1),
-    (2, S2, t2),
-    (3, S3, t3),
-    (4, S4, t4),
-    (5, S5, t5)
-);
+impl_sled_transactional!((0, S0));
+impl_sled_transactional!((0, S0), (1, S1));
+impl_sled_transactional!((0, S0), (1, S1), (2, S2));
+impl_sled_transactional!((0, S0), (1, S1), (2, S2), (3, S3));
+impl_sled_transactional!((0, S0), (1, S1), (2, S2), (3, S3), (4, S4));
+impl_sled_transactional!((0, S0), (1, S1), (2, S2), (3, S3), (4, S4), (5, S5));
 
 #[cfg(test)]
 mod tests {
@@ -754,4 +828,52 @@ mod tests {
             _ => panic!("Expected TransactionError::Abort"),
         }
     }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_retry_sleeps_through_sleeper() {
+        use std::sync::Mutex;
+
+        let db = create_test_db().unwrap();
+        let tree1 = db.get_tree::<TestSchema1>().unwrap();
+        let sleeps = Mutex::new(Vec::new());
+        let sleeper = |duration| {
+            sleeps.lock().unwrap().push(duration);
+            std::future::ready(())
+        };
+
+        let backoff = ExponentialBackoff::new(1, 2.0, 100);
+        let result: TransactionResult<(), crate::error::Error> = block_on(
+            (&tree1,).transaction_with_retry_async(&backoff, &sleeper, 3, |(tx_tree1,)| {
+                tx_tree1.insert(&1, &TestValue::alice())?;
+                Err(sled::transaction::ConflictableTransactionError::Storage(
+                    sled::Error::Unsupported("always fails".into()),
+                ))
+            }),
+        );
+
+        assert!(matches!(result, Err(TransactionError::Storage(_))));
+        let expected: Vec<_> = [1, 2, 4].map(Duration::from_millis).into();
+        assert_eq!(*sleeps.lock().unwrap(), expected);
+        assert!(!tree1.contains_key(&1).unwrap());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_async_retry_commits_without_sleeping() {
+        let db = create_test_db().unwrap();
+        let tree1 = db.get_tree::<TestSchema1>().unwrap();
+        let sleeper = |_| -> std::future::Ready<()> { panic!("should not sleep") };
+
+        let backoff = ConstantBackoff::new(1);
+        let result: TransactionResult<(), crate::error::Error> = block_on(
+            (&tree1,).transaction_with_retry_async(&backoff, &sleeper, 3, |(tx_tree1,)| {
+                tx_tree1.insert(&1, &TestValue::alice())?;
+                Ok(())
+            }),
+        );
+
+        assert!(result.is_ok());
+        assert!(tree1.contains_key(&1).unwrap());
+    }
 }
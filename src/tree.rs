@@ -1,18 +1,44 @@
 use std::{
+    borrow::Borrow,
     marker::PhantomData,
     ops::{Bound, RangeBounds},
+    sync::Arc,
 };
 
-use sled::{IVec, Iter, Tree, transaction::TransactionalTree};
+use sled::{IVec, Iter, Transactional, Tree, transaction::TransactionalTree};
+
+#[cfg(feature = "checksum")]
+use crate::checksum::ChecksummedValue;
+use crate::{
+    KeyCodec, KeyLookup, Schema, ValueCodec,
+    batch::SledBatch,
+    codec::{CodecContext, KeyPrefix, LegacySignedKey},
+    encryption::KeyProvider,
+    error::{Error, Result},
+    flush::{FlushPolicy, Flusher},
+    index::{AttachedIndexes, IndexViews, TreeIndexes, conflictable},
+    page::{Cursor, Direction, Page},
+    versioned::UpgradableValue,
+    watch::Subscriber,
+};
+
+pub(crate) type DecodedValue<S> = <<S as Schema>::Value as ValueCodec<S>>::Decoded;
 
-use crate::{KeyCodec, Schema, ValueCodec, batch::SledBatch, error::Result};
+/// Shared handle to the key provider of the database a tree belongs to.
+type SharedKeyProvider = Option<Arc<dyn KeyProvider>>;
 
-type DecodedValue<S> = <<S as Schema>::Value as ValueCodec<S>>::Decoded;
+/// Builds the codec context for a tree's key provider.
+fn codec_context(key_provider: &SharedKeyProvider) -> CodecContext<'_> {
+    CodecContext::new(key_provider.as_deref())
+}
 
 /// Decodes a raw key-value pair into typed schema types.
-fn decode_pair<S: Schema>((k, v): (IVec, IVec)) -> Result<(S::Key, DecodedValue<S>)> {
+fn decode_pair<S: Schema>(
+    ctx: &CodecContext<'_>,
+    (k, v): (IVec, IVec),
+) -> Result<(S::Key, DecodedValue<S>)> {
     let key = S::Key::decode_key(&k)?;
-    let value = S::Value::decode_value(v)?;
+    let value = S::Value::decode_value_with(v, &ctx.with_key(&k))?;
     Ok((key, value))
 }
 
@@ -26,56 +52,181 @@ fn key_bound<S: Schema>(k: Bound<&S::Key>) -> Result<Bound<Vec<u8>>> {
     Ok(bound)
 }
 
+/// The outcome of [`SledTree::compare_and_swap`].
+pub type CasResult<S> = std::result::Result<(), CompareAndSwapError<S>>;
+
+/// A compare-and-swap that failed because the stored value was not the
+/// expected one.
+pub struct CompareAndSwapError<S: Schema> {
+    /// The value stored at the time of the swap, or `None` if the key was
+    /// absent.
+    pub current: Option<DecodedValue<S>>,
+    /// The value that was not written.
+    pub proposed: Option<S::Value>,
+}
+
+impl<S: Schema> std::fmt::Debug for CompareAndSwapError<S>
+where
+    DecodedValue<S>: std::fmt::Debug,
+    S::Value: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CompareAndSwapError")
+            .field("current", &self.current)
+            .field("proposed", &self.proposed)
+            .finish()
+    }
+}
+
+impl<S: Schema> std::fmt::Display for CompareAndSwapError<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "compare and swap conflict in '{}'", S::TREE_NAME.0)
+    }
+}
+
+impl<S: Schema> std::error::Error for CompareAndSwapError<S>
+where
+    DecodedValue<S>: std::fmt::Debug,
+    S::Value: std::fmt::Debug,
+{
+}
+
 /// Type-safe wrapper around a sled tree with schema-enforced operations.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct SledTree<S: Schema> {
     pub(crate) inner: Tree,
+    pub(crate) key_provider: SharedKeyProvider,
+    pub(crate) flusher: Arc<Flusher>,
+    /// Indexes attached to the tree, shared by all handles of the tree.
+    pub(crate) indexes: Arc<TreeIndexes>,
     _phantom: PhantomData<S>,
 }
 
+// Not derived, as cloning a handle must not require `S: Clone`.
+impl<S: Schema> Clone for SledTree<S> {
+    fn clone(&self) -> Self {
+        Self::from_parts(
+            self.inner.clone(),
+            self.key_provider.clone(),
+            self.flusher.clone(),
+            self.indexes.clone(),
+        )
+    }
+}
+
 impl<S: Schema> SledTree<S> {
     /// Creates a new typed tree wrapper.
     pub fn new(inner: Tree) -> Self {
+        Self::from_parts(inner, None, Arc::default(), Arc::default())
+    }
+
+    /// Creates a typed tree wrapper from the state shared by its database.
+    pub(crate) fn from_parts(
+        inner: Tree,
+        key_provider: SharedKeyProvider,
+        flusher: Arc<Flusher>,
+        indexes: Arc<TreeIndexes>,
+    ) -> Self {
         Self {
             inner,
+            key_provider,
+            flusher,
+            indexes,
             _phantom: PhantomData,
         }
     }
 
+    /// Returns this handle with its own flush policy.
+    ///
+    /// Clones of the returned handle share the policy; other handles of the
+    /// same tree keep the database's policy.
+    pub fn with_flush_policy(self, policy: FlushPolicy) -> Self {
+        Self {
+            flusher: Arc::new(Flusher::new(policy)),
+            ..self
+        }
+    }
+
+    /// Returns the flush policy of this handle.
+    pub fn flush_policy(&self) -> FlushPolicy {
+        self.flusher.policy()
+    }
+
+    /// Flushes the tree's pending writes to disk, returning the number of
+    /// bytes flushed.
+    ///
+    /// Fails without flushing if a flush started by [`FlushPolicy::Async`]
+    /// failed since the last call, so call it again to retry.
+    pub fn flush(&self) -> Result<usize> {
+        self.flusher.take_failure()?;
+        Ok(self.inner.flush()?)
+    }
+
+    /// Asynchronously flushes the tree's pending writes to disk, returning
+    /// the number of bytes flushed.
+    ///
+    /// Reports failed [`FlushPolicy::Async`] flushes like [`SledTree::flush`].
+    #[cfg(feature = "async")]
+    pub async fn flush_async(&self) -> Result<usize> {
+        self.flusher.take_failure()?;
+        Ok(self.inner.flush_async().await?)
+    }
+
+    /// Returns the codec context of this tree.
+    pub(crate) fn ctx(&self) -> CodecContext<'_> {
+        codec_context(&self.key_provider)
+    }
+
+    /// Decodes a raw entry of this tree.
+    pub(crate) fn decode_entry(&self, pair: (IVec, IVec)) -> Result<(S::Key, DecodedValue<S>)> {
+        decode_pair::<S>(&self.ctx(), pair)
+    }
+
     /// Inserts a key-value pair into the tree.
     pub fn insert(&self, key: &S::Key, value: &S::Value) -> Result<()> {
         let key = key.encode_key()?;
-        let value = value.encode_value()?;
-        self.inner.insert(key, value)?;
+        let value = value.encode_value_with(&self.ctx().with_key(&key))?;
+        self.write_raw(&key, Some(value.into()))?;
 
-        self.inner.flush()?;
+        self.flusher.after_write(&self.inner)?;
         Ok(())
     }
 
     /// Retrieves a value for the given key.
-    pub fn get(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
-        let key = key.encode_key()?;
-        let val = self.inner.get(key)?;
-        Ok(val.map(S::Value::decode_value).transpose()?)
+    ///
+    /// The key may be given in any form the key type borrows as, such as a
+    /// `str` for `String` keys, see [`KeyLookup`].
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<DecodedValue<S>>>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        let key = key.encode_lookup_key()?;
+        let val = self.inner.get(&key)?;
+        Ok(val
+            .map(|v| S::Value::decode_value_with(v, &self.ctx().with_key(&key)))
+            .transpose()?)
     }
 
     /// Removes a key-value pair from the tree.
     pub fn remove(&self, key: &S::Key) -> Result<()> {
         let key = key.encode_key()?;
-        self.inner.remove(key)?;
+        self.write_raw(&key, None)?;
 
-        self.inner.flush()?;
+        self.flusher.after_write(&self.inner)?;
         Ok(())
     }
 
     /// Removes a key-value pair from the tree and returns the previous value.
     pub fn take(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
         let key = key.encode_key()?;
-        let old_value = self.inner.remove(key)?;
+        let old_value = self.write_raw(&key, None)?;
 
-        self.inner.flush()?;
+        self.flusher.after_write(&self.inner)?;
 
-        Ok(old_value.map(S::Value::decode_value).transpose()?)
+        Ok(old_value
+            .map(|v| S::Value::decode_value_with(v, &self.ctx().with_key(&key)))
+            .transpose()?)
     }
 
     /// Returns true if the tree contains no key-value pairs.
@@ -84,68 +235,683 @@ impl<S: Schema> SledTree<S> {
     }
 
     /// Returns `true` if the `SledTree` contains a value for the specified key
-    pub fn contains_key(&self, key: &S::Key) -> Result<bool> {
-        let key = key.encode_key()?;
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        let key = key.encode_lookup_key()?;
         Ok(self.inner.contains_key(key)?)
     }
 
     /// Returns the first key-value pair in the tree.
     pub fn first(&self) -> Result<Option<(S::Key, DecodedValue<S>)>> {
-        self.inner.first()?.map(decode_pair::<S>).transpose()
+        let ctx = self.ctx();
+        self.inner
+            .first()?
+            .map(|pair| decode_pair::<S>(&ctx, pair))
+            .transpose()
     }
 
     /// Returns the last key-value pair in the tree.
     pub fn last(&self) -> Result<Option<(S::Key, DecodedValue<S>)>> {
-        self.inner.last()?.map(decode_pair::<S>).transpose()
+        let ctx = self.ctx();
+        self.inner
+            .last()?
+            .map(|pair| decode_pair::<S>(&ctx, pair))
+            .transpose()
+    }
+
+    /// Returns the entry with the greatest key less than `key`.
+    pub fn get_lt(&self, key: &S::Key) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        let entry = self.inner.get_lt(key.encode_key()?)?;
+        self.decode_optional(entry)
+    }
+
+    /// Returns the entry with the least key greater than `key`.
+    pub fn get_gt(&self, key: &S::Key) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        let entry = self.inner.get_gt(key.encode_key()?)?;
+        self.decode_optional(entry)
+    }
+
+    /// Returns the entry with the greatest key less than or equal to `key`.
+    pub fn floor(&self, key: &S::Key) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        let entry = self.inner.range(..=key.encode_key()?).next_back();
+        self.decode_optional(entry.transpose()?)
+    }
+
+    /// Returns the entry with the least key greater than or equal to `key`.
+    pub fn ceiling(&self, key: &S::Key) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        let entry = self.inner.range(key.encode_key()?..).next();
+        self.decode_optional(entry.transpose()?)
+    }
+
+    /// Atomically removes and returns the entry with the least key.
+    pub fn pop_min(&self) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        self.pop_range(.., Direction::Forward)
+    }
+
+    /// Atomically removes and returns the entry with the greatest key.
+    pub fn pop_max(&self) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        self.pop_range(.., Direction::Backward)
+    }
+
+    /// Atomically removes and returns the entry with the least key within
+    /// `range`.
+    pub fn pop_min_range<R>(&self, range: R) -> Result<Option<(S::Key, DecodedValue<S>)>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        self.pop_range(range, Direction::Forward)
+    }
+
+    /// Atomically removes and returns the entry with the greatest key within
+    /// `range`.
+    pub fn pop_max_range<R>(&self, range: R) -> Result<Option<(S::Key, DecodedValue<S>)>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        self.pop_range(range, Direction::Backward)
+    }
+
+    /// Removes the first entry of `range` in `direction`, retrying when a
+    /// concurrent writer changes it first.
+    fn pop_range<R>(
+        &self,
+        range: R,
+        direction: Direction,
+    ) -> Result<Option<(S::Key, DecodedValue<S>)>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        let start = key_bound::<S>(range.start_bound())?;
+        let end = key_bound::<S>(range.end_bound())?;
+        let entry = loop {
+            let mut entries = self.inner.range((start.clone(), end.clone()));
+            let entry = match direction {
+                Direction::Forward => entries.next(),
+                Direction::Backward => entries.next_back(),
+            };
+            let Some((key, value)) = entry.transpose()? else {
+                break None;
+            };
+            if self.swap_raw(&key, Some(&value), None)?.is_ok() {
+                break Some((key, value));
+            }
+        };
+        if entry.is_some() {
+            self.flusher.after_write(&self.inner)?;
+        }
+        self.decode_optional(entry)
+    }
+
+    /// Decodes an entry that may be absent.
+    fn decode_optional(
+        &self,
+        entry: Option<(IVec, IVec)>,
+    ) -> Result<Option<(S::Key, DecodedValue<S>)>> {
+        entry.map(|pair| self.decode_entry(pair)).transpose()
     }
 
     /// Compares and swaps only if the value equals the old value.
+    ///
+    /// Values are compared in their encoded form, or by their
+    /// [`ValueCodec::stable_form`] for codecs without a stable encoding. If
+    /// the stored value differs, nothing is written and the inner result is
+    /// a [`CompareAndSwapError`] with the decoded current value.
     pub fn compare_and_swap(
         &self,
         key: S::Key,
         old: Option<S::Value>,
         new: Option<S::Value>,
-    ) -> Result<()> {
+    ) -> Result<CasResult<S>> {
         let key = key.encode_key()?;
-        let old = old.as_ref().map(S::Value::encode_value).transpose()?;
-        let new = new.as_ref().map(S::Value::encode_value).transpose()?;
-        self.inner.compare_and_swap(key, old, new)??;
-        self.inner.flush()?;
-        Ok(())
+        let ctx = self.ctx().with_key(&key);
+        let encoded = new
+            .as_ref()
+            .map(|v| v.encode_value_with(&ctx))
+            .transpose()?;
+        let swapped = if S::Value::STABLE_ENCODING {
+            let old = old
+                .as_ref()
+                .map(|v| v.encode_value_with(&ctx))
+                .transpose()?;
+            self.swap_raw(&key, old.as_deref(), encoded.map(IVec::from))?
+        } else {
+            self.swap_by_stable_form(&key, old.as_ref(), encoded)?
+        };
+        if let Err(current) = swapped {
+            let current = current
+                .map(|v| S::Value::decode_value_with(v, &ctx))
+                .transpose()?;
+            return Ok(Err(CompareAndSwapError {
+                current,
+                proposed: new,
+            }));
+        }
+        self.flusher.after_write(&self.inner)?;
+        Ok(Ok(()))
+    }
+
+    /// Swaps the raw value of `key` for `new` if the stored value has the
+    /// stable form of `old`, returning the stored value otherwise.
+    fn swap_by_stable_form(
+        &self,
+        key: &[u8],
+        old: Option<&S::Value>,
+        new: Option<Vec<u8>>,
+    ) -> Result<std::result::Result<(), Option<IVec>>> {
+        let ctx = self.ctx().with_key(key);
+        let expected = old.map(|v| v.encode_stable_form(&ctx)).transpose()?;
+        let mut current = self.inner.get(key)?;
+        loop {
+            let stored = current
+                .clone()
+                .map(|v| S::Value::stable_form(v, &ctx))
+                .transpose()?;
+            if stored.as_deref() != expected.as_deref() {
+                return Ok(Err(current));
+            }
+            // Swap against the exact stored bytes, retrying if a concurrent
+            // write replaced them since they were read.
+            match self.swap_raw(key, current.as_deref(), new.clone().map(IVec::from))? {
+                Ok(()) => return Ok(Ok(())),
+                Err(stored) => current = stored,
+            }
+        }
+    }
+
+    /// Atomically replaces the value of `key` with the result of `f` and
+    /// returns the previous value.
+    ///
+    /// `f` receives the current value, or `None` if the key is absent, and
+    /// returns the new value, or `None` to remove the key. It is called again
+    /// with the latest value if a concurrent write changes the key first.
+    /// Codec errors while decoding the current value or encoding the new one
+    /// are returned without writing anything.
+    pub fn fetch_and_update<F>(&self, key: &S::Key, f: F) -> Result<Option<DecodedValue<S>>>
+    where
+        F: FnMut(Option<DecodedValue<S>>) -> Option<S::Value>,
+    {
+        let key = key.encode_key()?;
+        let (old, _) = self.update_raw(&key, f)?;
+        let ctx = self.ctx().with_key(&key);
+        Ok(old
+            .map(|v| S::Value::decode_value_with(v, &ctx))
+            .transpose()?)
+    }
+
+    /// Atomically replaces the value of `key` with the result of `f` and
+    /// returns the new value.
+    ///
+    /// Behaves like [`SledTree::fetch_and_update`] otherwise.
+    pub fn update_and_fetch<F>(&self, key: &S::Key, f: F) -> Result<Option<DecodedValue<S>>>
+    where
+        F: FnMut(Option<DecodedValue<S>>) -> Option<S::Value>,
+    {
+        let key = key.encode_key()?;
+        let (_, new) = self.update_raw(&key, f)?;
+        let ctx = self.ctx().with_key(&key);
+        Ok(new
+            .map(|v| S::Value::decode_value_with(v, &ctx))
+            .transpose()?)
+    }
+
+    /// Swaps the raw value of `key` for the encoded result of `f` until no
+    /// concurrent write intervenes, returning the raw old and new values.
+    fn update_raw<F>(&self, key: &[u8], mut f: F) -> Result<(Option<IVec>, Option<IVec>)>
+    where
+        F: FnMut(Option<DecodedValue<S>>) -> Option<S::Value>,
+    {
+        let ctx = self.ctx().with_key(key);
+        let mut current = self.inner.get(key)?;
+        loop {
+            let decoded = current
+                .clone()
+                .map(|v| S::Value::decode_value_with(v, &ctx))
+                .transpose()?;
+            let new = f(decoded)
+                .map(|v| v.encode_value_with(&ctx).map(IVec::from))
+                .transpose()?;
+            match self.swap_raw(key, current.as_deref(), new.clone())? {
+                Ok(()) => {
+                    self.flusher.after_write(&self.inner)?;
+                    return Ok((current, new));
+                }
+                Err(stored) => current = stored,
+            }
+        }
+    }
+
+    /// Creates an empty batch for this tree.
+    ///
+    /// Unlike [`SledBatch::new`], the batch encodes values with this tree's
+    /// codec context, which encrypted values require.
+    pub fn batch(&self) -> SledBatch<S> {
+        SledBatch::with_key_provider(self.key_provider.clone())
     }
 
     /// Applies a batch of operations atomically.
     pub fn apply_batch(&self, batch: SledBatch<S>) -> Result<()> {
-        self.inner.apply_batch(batch.inner)?;
-        let _ = self.inner.flush();
+        let ctx = self.ctx();
+        self.write(
+            |tree| Ok(tree.apply_batch(batch.to_sled())?),
+            |tree, indexes| {
+                for (key, value) in &batch.ops {
+                    indexes.write(S::TREE_NAME.0, tree, key, value.clone(), &ctx)?;
+                }
+                Ok(())
+            },
+        )?;
+        self.flusher.after_write(&self.inner)?;
         Ok(())
     }
 
+    /// Runs a write to this tree, which attaching an index waits for.
+    ///
+    /// Without attached indexes, `plain` writes to the tree directly.
+    /// Otherwise `indexed` runs in one transaction over the tree and its
+    /// index trees, and writes through the index views to keep them in sync.
+    fn write<R>(
+        &self,
+        plain: impl FnOnce(&Tree) -> Result<R>,
+        indexed: impl Fn(&TransactionalTree, &IndexViews) -> Result<R>,
+    ) -> Result<R> {
+        let attached = self.indexes.read();
+        if attached.is_empty() {
+            return plain(&self.inner);
+        }
+        let trees: Vec<Tree> = std::iter::once(self.inner.clone())
+            .chain(attached.trees().cloned())
+            .collect();
+        trees
+            .as_slice()
+            .transaction(|views| {
+                let mut views = views.iter();
+                let primary = views.next().expect("primary tree view");
+                indexed(primary, &attached.views(&mut views)).map_err(conflictable)
+            })
+            .map_err(Error::from_transaction)
+    }
+
+    /// Writes or removes the raw value of `key`, returning the previous raw
+    /// value.
+    fn write_raw(&self, key: &[u8], value: Option<IVec>) -> Result<Option<IVec>> {
+        let ctx = self.ctx();
+        self.write(
+            |tree| match &value {
+                Some(value) => Ok(tree.insert(key, value.clone())?),
+                None => Ok(tree.remove(key)?),
+            },
+            |tree, indexes| indexes.write(S::TREE_NAME.0, tree, key, value.clone(), &ctx),
+        )
+    }
+
+    /// Swaps the raw value of `key` for `new` if it is `old`, returning the
+    /// stored value otherwise.
+    fn swap_raw(
+        &self,
+        key: &[u8],
+        old: Option<&[u8]>,
+        new: Option<IVec>,
+    ) -> Result<std::result::Result<(), Option<IVec>>> {
+        let ctx = self.ctx();
+        self.write(
+            |tree| {
+                Ok(tree
+                    .compare_and_swap(key, old, new.clone())?
+                    .map_err(|err| err.current))
+            },
+            |tree, indexes| {
+                let current = tree.get(key)?;
+                if current.as_deref() != old {
+                    return Ok(Err(current));
+                }
+                indexes.write(S::TREE_NAME.0, tree, key, new.clone(), &ctx)?;
+                Ok(Ok(()))
+            },
+        )
+    }
+
     /// Returns an iterator over all key-value pairs in the tree.
     pub fn iter(&self) -> SledTreeIter<S> {
         SledTreeIter {
             inner: self.inner.iter(),
+            key_provider: self.key_provider.clone(),
             _phantom: PhantomData,
         }
     }
 
     /// Returns an iterator over key-value pairs within the specified range.
     pub fn range<R>(&self, range: R) -> Result<SledTreeIter<S>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        Ok(SledTreeIter {
+            inner: self.raw_range(range)?,
+            key_provider: self.key_provider.clone(),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns an iterator over all keys in the tree, without decoding any
+    /// values.
+    pub fn keys(&self) -> SledTreeKeys<S> {
+        SledTreeKeys {
+            inner: self.inner.iter(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over all values in the tree, without decoding any
+    /// keys.
+    pub fn values(&self) -> SledTreeValues<S> {
+        SledTreeValues {
+            inner: self.inner.iter(),
+            key_provider: self.key_provider.clone(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns an iterator over the keys within the specified range, without
+    /// decoding any values.
+    pub fn range_keys<R>(&self, range: R) -> Result<SledTreeKeys<S>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        Ok(SledTreeKeys {
+            inner: self.raw_range(range)?,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns an iterator over the values of keys within the specified
+    /// range, without decoding any keys.
+    pub fn range_values<R>(&self, range: R) -> Result<SledTreeValues<S>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        Ok(SledTreeValues {
+            inner: self.raw_range(range)?,
+            key_provider: self.key_provider.clone(),
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns a raw iterator over the entries within `range`.
+    fn raw_range<R>(&self, range: R) -> Result<Iter>
     where
         R: RangeBounds<S::Key>,
     {
         let start = key_bound::<S>(range.start_bound())?;
         let end = key_bound::<S>(range.end_bound())?;
+        Ok(self.inner.range((start, end)))
+    }
+
+    /// Returns an iterator over key-value pairs whose keys start with `prefix`.
+    ///
+    /// The prefix is a typed leading portion of the key, such as
+    /// [`Prefix`](crate::Prefix)`((account_id,))` for `(u32, u64)` keys or a
+    /// `str` for `String` keys.
+    pub fn scan_prefix<P>(&self, prefix: &P) -> Result<SledTreeIter<S>>
+    where
+        P: KeyPrefix<S, S::Key> + ?Sized,
+    {
+        let prefix = prefix.encode_prefix()?;
         Ok(SledTreeIter {
-            inner: self.inner.range((start, end)),
+            inner: self.inner.scan_prefix(prefix),
+            key_provider: self.key_provider.clone(),
             _phantom: PhantomData,
         })
     }
+
+    /// Returns up to `limit` entries following `cursor` in `direction`.
+    ///
+    /// Starts at the first entry in `direction` when `cursor` is `None`. The
+    /// returned page carries the cursor of the next page, or `None` once no
+    /// entries are left. Cursors are key positions, so entries inserted or
+    /// removed elsewhere in the tree do not shift later pages.
+    ///
+    /// Fails with [`Error::InvalidCursor`] if `limit` is `0`, as an empty
+    /// page could not carry the position of the next one.
+    pub fn page(
+        &self,
+        cursor: Option<Cursor<S>>,
+        limit: usize,
+        direction: Direction,
+    ) -> Result<Page<S>> {
+        if limit == 0 {
+            return Err(Error::InvalidCursor("page limit must be at least 1".into()));
+        }
+        let after = cursor.map_or(Bound::Unbounded, |cursor| {
+            Bound::Excluded(cursor.into_raw())
+        });
+        match direction {
+            Direction::Forward => {
+                self.collect_page(self.inner.range((after, Bound::Unbounded)), limit)
+            }
+            Direction::Backward => {
+                self.collect_page(self.inner.range((Bound::Unbounded, after)).rev(), limit)
+            }
+        }
+    }
+
+    /// Decodes the first `limit` entries of `entries` into a page.
+    fn collect_page(
+        &self,
+        mut entries: impl Iterator<Item = sled::Result<(IVec, IVec)>>,
+        limit: usize,
+    ) -> Result<Page<S>> {
+        let mut items = Vec::with_capacity(limit);
+        let mut last = None;
+        for entry in entries.by_ref().take(limit) {
+            let (key, value) = entry?;
+            last = Some(key.to_vec());
+            items.push(self.decode_entry((key, value))?);
+        }
+        let next = match last {
+            Some(key) if entries.next().transpose()?.is_some() => Some(Cursor::new(key)),
+            _ => None,
+        };
+        Ok(Page { items, next })
+    }
+
+    /// Subscribes to every change of the tree made after this call.
+    ///
+    /// With the `async` feature the subscriber is also a `Stream` of the
+    /// changes, to be awaited instead of blocking.
+    pub fn watch(&self) -> Subscriber<S> {
+        Subscriber::new(
+            &self.inner,
+            (Bound::Unbounded, Bound::Unbounded),
+            self.key_provider.clone(),
+        )
+    }
+
+    /// Subscribes to the changes of keys within `range` made after this call.
+    pub fn watch_range<R>(&self, range: R) -> Result<Subscriber<S>>
+    where
+        R: RangeBounds<S::Key>,
+    {
+        let start = key_bound::<S>(range.start_bound())?.map(IVec::from);
+        let end = key_bound::<S>(range.end_bound())?.map(IVec::from);
+        Ok(Subscriber::new(
+            &self.inner,
+            (start, end),
+            self.key_provider.clone(),
+        ))
+    }
+}
+
+impl<S: Schema> SledTree<S>
+where
+    S::Key: LegacySignedKey<S>,
+{
+    /// Rewrites signed integer keys from the legacy layout into the current one,
+    /// recording the migration in `markers`.
+    ///
+    /// The keys are rewritten in the same transaction as the record, and a
+    /// tree that already has one is left alone, as the layouts cannot be
+    /// told apart. Returns the number of rewritten entries.
+    ///
+    /// The keys are collected before the transaction, so the tree must not
+    /// be written to meanwhile. Values are re-read inside the transaction so
+    /// that an entry updated or removed since the scan is not reverted.
+    pub(crate) fn migrate_legacy_signed_keys(&self, markers: &Tree) -> Result<usize> {
+        let marker = S::TREE_NAME.0.as_bytes();
+        if markers.contains_key(marker)? {
+            return Ok(0);
+        }
+        let mut entries = Vec::new();
+        for entry in self.inner.iter() {
+            let raw_key = entry?.0;
+            let key = S::Key::decode_legacy_key(&raw_key)?;
+            entries.push((raw_key, key.encode_key()?));
+        }
+
+        // The indexes of the tree move their entries along with the keys.
+        let attached = self.indexes.read();
+        let trees: Vec<Tree> = [self.inner.clone(), markers.clone()]
+            .into_iter()
+            .chain(attached.trees().cloned())
+            .collect();
+        let ctx = self.ctx();
+        let migrated = trees
+            .as_slice()
+            .transaction(|views| {
+                let mut views = views.iter();
+                let tree = views.next().expect("primary tree view");
+                let markers = views.next().expect("marker tree view");
+                let indexes = attached.views(&mut views);
+                if markers.get(marker)?.is_some() {
+                    return Ok(0);
+                }
+                let write = |key: &[u8], value| {
+                    indexes
+                        .write(S::TREE_NAME.0, tree, key, value, &ctx)
+                        .map_err(conflictable)
+                };
+                // Removals go first so that a new key colliding with another
+                // entry's legacy key is overwritten by the insert rather than
+                // dropped.
+                let mut moved = Vec::with_capacity(entries.len());
+                for (raw_key, new_key) in &entries {
+                    if let Some(value) = write(raw_key, None)? {
+                        moved.push((new_key, value));
+                    }
+                }
+                for (new_key, value) in moved.iter().cloned() {
+                    write(new_key, Some(value))?;
+                }
+                markers.insert(marker, &[])?;
+                Ok(moved.len())
+            })
+            .map_err(Error::from_transaction)?;
+        drop(attached);
+
+        self.flusher.after_write(&self.inner)?;
+        Ok(migrated)
+    }
+}
+
+impl<S: Schema> SledTree<S>
+where
+    S::Value: UpgradableValue<S>,
+{
+    /// Retrieves a value, rewriting it in the current layout if it was
+    /// stored by an older version.
+    pub fn get_and_upgrade(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
+        let key = key.encode_key()?;
+        let Some(raw) = self.inner.get(&key)? else {
+            return Ok(None);
+        };
+        self.upgrade_read(&key, raw)
+    }
+
+    /// Upgrades the value `raw` read from `key` and returns the value stored
+    /// afterwards, which is that of a concurrent write if one replaced `raw`
+    /// first.
+    pub(crate) fn upgrade_read(&self, key: &[u8], raw: IVec) -> Result<Option<DecodedValue<S>>> {
+        let ctx = self.ctx().with_key(key);
+        if S::Value::is_current(&raw) {
+            return Ok(Some(S::Value::decode_value_with(raw, &ctx)?));
+        }
+
+        let stored = match self.upgrade_raw(key, raw)? {
+            Ok(upgraded) => {
+                self.flusher.after_write(&self.inner)?;
+                Some(upgraded.into())
+            }
+            Err(current) => current,
+        };
+        Ok(stored
+            .map(|v| S::Value::decode_value_with(v, &ctx))
+            .transpose()?)
+    }
+
+    /// Rewrites every value stored by an older version in the current layout.
+    ///
+    /// Returns the number of rewritten values. Values written concurrently
+    /// are left alone.
+    pub fn upgrade_all(&self) -> Result<usize> {
+        let mut upgraded = 0;
+        for entry in self.inner.iter() {
+            let (key, raw) = entry?;
+            if !S::Value::is_current(&raw) && self.upgrade_raw(&key, raw)?.is_ok() {
+                upgraded += 1;
+            }
+        }
+
+        if upgraded > 0 {
+            self.flusher.after_write(&self.inner)?;
+        }
+        Ok(upgraded)
+    }
+
+    /// Decodes a stale value, re-encodes it and swaps it in if it is unchanged.
+    ///
+    /// Returns the upgraded value, or the current value if a concurrent write
+    /// replaced `raw` first.
+    pub(crate) fn upgrade_raw(
+        &self,
+        key: &[u8],
+        raw: IVec,
+    ) -> Result<std::result::Result<Vec<u8>, Option<IVec>>> {
+        let ctx = self.ctx().with_key(key);
+        let value = S::Value::decode_value_with(raw.clone(), &ctx)?;
+        let upgraded = S::Value::from_decoded(value).encode_value_with(&ctx)?;
+        Ok(self
+            .swap_raw(key, Some(&raw), Some(upgraded.as_slice().into()))?
+            .map(|()| upgraded))
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl<S: Schema> SledTree<S>
+where
+    S::Value: ChecksummedValue,
+{
+    /// Checks the checksum of every value in the tree without decoding it.
+    ///
+    /// Returns the encoded keys of the entries whose checksum does not match.
+    pub fn verify_checksums(&self) -> Result<Vec<Vec<u8>>> {
+        let mut corrupted = Vec::new();
+        for entry in self.inner.iter() {
+            let (key, value) = entry?;
+            if !S::Value::verify_checksum(&value) {
+                corrupted.push(key.to_vec());
+            }
+        }
+        Ok(corrupted)
+    }
 }
 
 /// Type-safe wrapper around sled's transactional tree.
 pub struct SledTransactionalTree<S: Schema> {
-    inner: TransactionalTree,
+    pub(crate) inner: TransactionalTree,
+    key_provider: SharedKeyProvider,
+    pub(crate) flusher: Arc<Flusher>,
+    indexes: IndexViews,
     _phantom: PhantomData<S>,
 }
 
@@ -163,53 +929,98 @@ impl<S: Schema> SledTransactionalTree<S> {
     pub fn new(inner: TransactionalTree) -> Self {
         Self {
             inner,
+            key_provider: None,
+            flusher: Arc::default(),
+            indexes: IndexViews::default(),
             _phantom: PhantomData,
         }
     }
 
+    /// Creates a transactional tree wrapper sharing the state of `tree`,
+    /// taking the views of the tree and of its `attached` indexes from
+    /// `views`.
+    pub(crate) fn for_tree<'v>(
+        views: &mut impl Iterator<Item = &'v TransactionalTree>,
+        tree: &SledTree<S>,
+        attached: &AttachedIndexes,
+    ) -> Self {
+        Self {
+            inner: views.next().expect("tree view").clone(),
+            key_provider: tree.key_provider.clone(),
+            flusher: tree.flusher.clone(),
+            indexes: attached.views(views),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Writes or removes the raw value of `key` and the entries of the
+    /// attached indexes, returning the previous raw value.
+    fn write_raw(&self, key: &[u8], value: Option<IVec>) -> Result<Option<IVec>> {
+        let old = self
+            .indexes
+            .write(S::TREE_NAME.0, &self.inner, key, value, &self.ctx())?;
+        self.flusher.after_transactional_write(&self.inner);
+        Ok(old)
+    }
+
+    /// Returns the codec context of this tree.
+    pub(crate) fn ctx(&self) -> CodecContext<'_> {
+        codec_context(&self.key_provider)
+    }
+
     /// Inserts a key-value pair in the transaction.
     pub fn insert(&self, key: &S::Key, value: &S::Value) -> Result<()> {
         let key = key.encode_key()?;
-        let value = value.encode_value()?;
-        self.inner.insert(key, value)?;
-        self.inner.flush();
+        let value = value.encode_value_with(&self.ctx().with_key(&key))?;
+        self.write_raw(&key, Some(value.into()))?;
         Ok(())
     }
 
     /// Retrieves a value for the given key within the transaction.
-    pub fn get(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
-        let key = key.encode_key()?;
-        let val = self.inner.get(key)?;
-        Ok(val.map(S::Value::decode_value).transpose()?)
+    pub fn get<Q>(&self, key: &Q) -> Result<Option<DecodedValue<S>>>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        let key = key.encode_lookup_key()?;
+        let val = self.inner.get(&key)?;
+        Ok(val
+            .map(|v| S::Value::decode_value_with(v, &self.ctx().with_key(&key)))
+            .transpose()?)
     }
 
     /// Returns `true` if the `SledTree` contains a value for the specified key
-    pub fn contains_key(&self, key: &S::Key) -> Result<bool> {
-        let key = key.encode_key()?;
+    pub fn contains_key<Q>(&self, key: &Q) -> Result<bool>
+    where
+        S::Key: Borrow<Q>,
+        Q: KeyLookup<S> + ?Sized,
+    {
+        let key = key.encode_lookup_key()?;
         Ok(self.inner.get(key)?.is_some())
     }
 
     /// Removes a key-value pair within the transaction.
     pub fn remove(&self, key: &S::Key) -> Result<()> {
         let key = key.encode_key()?;
-        self.inner.remove(key)?;
-        self.inner.flush();
+        self.write_raw(&key, None)?;
         Ok(())
     }
 
     /// Removes a key-value pair within the transaction and returns the previous value.
     pub fn take(&self, key: &S::Key) -> Result<Option<DecodedValue<S>>> {
         let key = key.encode_key()?;
-        let old_value = self.inner.remove(key)?;
-        self.inner.flush();
+        let old_value = self.write_raw(&key, None)?;
 
-        Ok(old_value.map(S::Value::decode_value).transpose()?)
+        Ok(old_value
+            .map(|v| S::Value::decode_value_with(v, &self.ctx().with_key(&key)))
+            .transpose()?)
     }
 }
 
 /// A typed iterator over key-value pairs in a sled tree.
 pub struct SledTreeIter<S: Schema> {
     inner: Iter,
+    key_provider: SharedKeyProvider,
     _phantom: PhantomData<S>,
 }
 
@@ -225,30 +1036,112 @@ impl<S: Schema> std::fmt::Debug for SledTreeIter<S> {
 impl<S: Schema> Iterator for SledTreeIter<S> {
     type Item = Result<(S::Key, DecodedValue<S>)>;
 
+    fn next(&mut self) -> Option<Self::Item> {
+        let ctx = codec_context(&self.key_provider);
+        self.inner.next().map(|result| {
+            result
+                .map_err(Into::into)
+                .and_then(|pair| decode_pair::<S>(&ctx, pair))
+        })
+    }
+}
+
+impl<S: Schema> DoubleEndedIterator for SledTreeIter<S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let ctx = codec_context(&self.key_provider);
+        self.inner.next_back().map(|result| {
+            result
+                .map_err(Into::into)
+                .and_then(|pair| decode_pair::<S>(&ctx, pair))
+        })
+    }
+}
+
+/// A typed iterator over the keys of a sled tree.
+pub struct SledTreeKeys<S: Schema> {
+    inner: Iter,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Schema> std::fmt::Debug for SledTreeKeys<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SledTreeKeys")
+            .field("tree_name", &S::TREE_NAME.0)
+            .field("schema", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: Schema> Iterator for SledTreeKeys<S> {
+    type Item = Result<S::Key>;
+
     fn next(&mut self) -> Option<Self::Item> {
         self.inner
             .next()
-            .map(|result| result.map_err(Into::into).and_then(decode_pair::<S>))
+            .map(|result| Ok(S::Key::decode_key(&result?.0)?))
     }
 }
 
-impl<S: Schema> DoubleEndedIterator for SledTreeIter<S> {
+impl<S: Schema> DoubleEndedIterator for SledTreeKeys<S> {
     fn next_back(&mut self) -> Option<Self::Item> {
         self.inner
             .next_back()
-            .map(|result| result.map_err(Into::into).and_then(decode_pair::<S>))
+            .map(|result| Ok(S::Key::decode_key(&result?.0)?))
+    }
+}
+
+/// A typed iterator over the values of a sled tree.
+pub struct SledTreeValues<S: Schema> {
+    inner: Iter,
+    key_provider: SharedKeyProvider,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Schema> SledTreeValues<S> {
+    fn decode(&self, (k, v): (IVec, IVec)) -> Result<DecodedValue<S>> {
+        let ctx = codec_context(&self.key_provider);
+        Ok(S::Value::decode_value_with(v, &ctx.with_key(&k))?)
+    }
+}
+
+impl<S: Schema> std::fmt::Debug for SledTreeValues<S> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SledTreeValues")
+            .field("tree_name", &S::TREE_NAME.0)
+            .field("schema", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: Schema> Iterator for SledTreeValues<S> {
+    type Item = Result<DecodedValue<S>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next()?;
+        Some(entry.map_err(Into::into).and_then(|pair| self.decode(pair)))
+    }
+}
+
+impl<S: Schema> DoubleEndedIterator for SledTreeValues<S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let entry = self.inner.next_back()?;
+        Some(entry.map_err(Into::into).and_then(|pair| self.decode(pair)))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use rkyv::rancor::Error as RkyvError;
     use rkyv::util::AlignedVec;
     use rkyv::{Archive, Serialize};
-    use sled::IVec;
+    use sled::{IVec, transaction::ConflictableTransactionError};
 
     use super::*;
-    use crate::{CodecError, KeyCodec, RkyvView, TreeName, test_utils::*};
+    use crate::{
+        CodecError, KeyCodec, Prefix, Rkyv, RkyvView, TreeName,
+        codec::{ZeroCopy, decode_rkyv_view, encode_rkyv},
+        test_utils::*,
+        transaction::SledTransactional,
+    };
 
     fn create_test_tree() -> Result<SledTree<TestSchema1>> {
         create_temp_tree::<TestSchema1>()
@@ -273,21 +1166,11 @@ mod tests {
         type Decoded = RkyvView<AlignedVec, <Self as Archive>::Archived>;
 
         fn encode_value(&self) -> crate::CodecResult<Vec<u8>> {
-            rkyv::to_bytes::<RkyvError>(self)
-                .map(|bytes| bytes.into_vec())
-                .map_err(|source| CodecError::SerializationFailed {
-                    schema: ArchivedTestSchema::TREE_NAME.0,
-                    source: source.into(),
-                })
+            encode_rkyv::<ArchivedTestSchema, Self>(self)
         }
 
         fn decode_value(buf: IVec) -> crate::CodecResult<Self::Decoded> {
-            let mut aligned = AlignedVec::with_capacity(buf.len());
-            aligned.extend_from_slice(buf.as_ref());
-            RkyvView::try_new(aligned).map_err(|source| CodecError::DeserializationFailed {
-                schema: ArchivedTestSchema::TREE_NAME.0,
-                source: source.into(),
-            })
+            decode_rkyv_view::<ArchivedTestSchema, Self>(buf)
         }
     }
 
@@ -436,6 +1319,44 @@ mod tests {
         assert_eq!(items[2].0, 2);
     }
 
+    #[test]
+    fn test_keys_and_values() {
+        let tree = create_test_tree().unwrap();
+        for i in 1..=5 {
+            tree.insert(&i, &TestValue::new_with_name(i)).unwrap();
+        }
+
+        let keys: Vec<_> = tree.keys().map(|key| key.unwrap()).collect();
+        assert_eq!(keys, vec![1, 2, 3, 4, 5]);
+        let keys: Vec<_> = tree.range_keys(2..4).unwrap().rev().collect();
+        assert_eq!(
+            keys.into_iter().map(|key| key.unwrap()).collect::<Vec<_>>(),
+            vec![3, 2]
+        );
+
+        let values: Vec<_> = tree.values().map(|value| value.unwrap()).collect();
+        assert_eq!(values.len(), 5);
+        assert_test_values_eq(&TestValue::new_with_name(1), &values[0]);
+        let values: Vec<_> = tree.range_values(4..).unwrap().rev().collect();
+        assert_eq!(values.len(), 2);
+        assert_test_values_eq(&TestValue::new_with_name(5), values[0].as_ref().unwrap());
+    }
+
+    #[test]
+    fn test_keys_never_decode_values() {
+        let tree = create_test_tree().unwrap();
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        let key = <u32 as KeyCodec<TestSchema1>>::encode_key(&2).unwrap();
+        tree.inner.insert(key, vec![0xff_u8; 3]).unwrap();
+
+        assert!(tree.iter().any(|entry| entry.is_err()));
+        assert!(tree.values().any(|value| value.is_err()));
+        let keys: Result<Vec<_>> = tree.keys().collect();
+        assert_eq!(keys.unwrap(), vec![1, 2]);
+        let keys: Result<Vec<_>> = tree.range_keys(2..).unwrap().collect();
+        assert_eq!(keys.unwrap(), vec![2]);
+    }
+
     #[test]
     fn test_u32_key_ordering_large_values() {
         let tree = create_test_tree().unwrap();
@@ -611,22 +1532,112 @@ mod tests {
 
         // CAS on non-existent key with None expected
         tree.compare_and_swap(1, None, Some(TestValue::alice()))
+            .unwrap()
             .unwrap();
         let value = tree.get(&1).unwrap().unwrap();
         assert_test_values_eq(&value, &TestValue::alice());
 
         // CAS with correct old value
         tree.compare_and_swap(1, Some(TestValue::alice()), Some(TestValue::bob()))
+            .unwrap()
             .unwrap();
         let value = tree.get(&1).unwrap().unwrap();
         assert_test_values_eq(&value, &TestValue::bob());
 
         // CAS to remove (set to None)
         tree.compare_and_swap(1, Some(TestValue::bob()), None)
+            .unwrap()
             .unwrap();
         assert!(!tree.contains_key(&1).unwrap());
     }
 
+    #[test]
+    fn test_failed_compare_and_swap_returns_current_value() {
+        let tree = create_test_tree().unwrap();
+        tree.insert(&1, &TestValue::bob()).unwrap();
+
+        let err = tree
+            .compare_and_swap(1, Some(TestValue::alice()), Some(TestValue::charlie()))
+            .unwrap()
+            .unwrap_err();
+        assert_test_values_eq(&TestValue::bob(), &err.current.unwrap());
+        assert_test_values_eq(&TestValue::charlie(), &err.proposed.unwrap());
+        assert_test_values_eq(&TestValue::bob(), &tree.get(&1).unwrap().unwrap());
+
+        let err = tree
+            .compare_and_swap(2, Some(TestValue::alice()), None)
+            .unwrap()
+            .unwrap_err();
+        assert!(err.current.is_none());
+        assert!(err.proposed.is_none());
+        assert_eq!(err.to_string(), "compare and swap conflict in 'test1'");
+    }
+
+    #[test]
+    fn test_fetch_and_update() {
+        let tree = create_test_tree().unwrap();
+        let rename = |value: Option<TestValue>| {
+            Some(match value {
+                Some(value) => TestValue::new(value.id, &format!("{}!", value.name)),
+                None => TestValue::alice(),
+            })
+        };
+
+        assert!(tree.fetch_and_update(&1, rename).unwrap().is_none());
+        assert_test_values_eq(&TestValue::alice(), &tree.get(&1).unwrap().unwrap());
+
+        let old = tree.fetch_and_update(&1, rename).unwrap().unwrap();
+        assert_test_values_eq(&TestValue::alice(), &old);
+        let new = tree.update_and_fetch(&1, rename).unwrap().unwrap();
+        assert_test_values_eq(&TestValue::new(1, "Alice!!"), &new);
+
+        // Returning `None` removes the key.
+        assert!(tree.update_and_fetch(&1, |_| None).unwrap().is_none());
+        assert!(!tree.contains_key(&1).unwrap());
+    }
+
+    #[test]
+    fn test_update_surfaces_codec_errors() {
+        let tree = create_test_tree().unwrap();
+        let key = <u32 as KeyCodec<TestSchema1>>::encode_key(&1).unwrap();
+        tree.inner.insert(&key, vec![0xff_u8; 3]).unwrap();
+
+        let mut calls = 0;
+        let result = tree.fetch_and_update(&1, |_| {
+            calls += 1;
+            Some(TestValue::alice())
+        });
+        assert!(matches!(
+            result,
+            Err(crate::error::Error::CodecError(
+                CodecError::DeserializationFailed { .. }
+            ))
+        ));
+        assert_eq!(calls, 0);
+        assert_eq!(tree.inner.get(&key).unwrap().unwrap(), vec![0xff_u8; 3]);
+    }
+
+    #[test]
+    fn test_concurrent_update_and_fetch() {
+        let tree = create_test_tree().unwrap();
+        tree.insert(&1, &TestValue::new(0, "counter")).unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..25 {
+                        tree.update_and_fetch(&1, |value| {
+                            value.map(|value| TestValue::new(value.id + 1, &value.name))
+                        })
+                        .unwrap();
+                    }
+                });
+            }
+        });
+
+        assert_eq!(tree.get(&1).unwrap().unwrap().id, 100);
+    }
+
     #[test]
     fn test_overwrite_existing_key() {
         let tree = create_test_tree().unwrap();
@@ -697,6 +1708,317 @@ mod tests {
         assert_eq!(items[0].0, 5);
     }
 
+    #[derive(Debug, Clone)]
+    struct SignedKeySchema;
+
+    impl Schema for SignedKeySchema {
+        const TREE_NAME: TreeName = TreeName("signed_keys");
+        type Key = i64;
+        type Value = TestValue;
+    }
+
+    #[test]
+    fn test_signed_key_ordering() {
+        let tree = create_temp_tree::<SignedKeySchema>().unwrap();
+
+        for key in [5, -3, 0, i64::MIN, -1, i64::MAX] {
+            tree.insert(&key, &TestValue::alice()).unwrap();
+        }
+
+        let keys: Vec<_> = tree.iter().map(|item| item.unwrap().0).collect();
+        assert_eq!(keys, vec![i64::MIN, -3, -1, 0, 5, i64::MAX]);
+
+        assert_eq!(tree.first().unwrap().unwrap().0, i64::MIN);
+        assert_eq!(tree.last().unwrap().unwrap().0, i64::MAX);
+
+        let keys: Vec<_> = tree
+            .range(-3..=0)
+            .unwrap()
+            .rev()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![0, -1, -3]);
+    }
+
+    #[test]
+    fn test_neighbor_lookups() {
+        let tree = create_temp_tree::<SignedKeySchema>().unwrap();
+        for key in [-300, -1, 0, 256] {
+            tree.insert(&key, &TestValue::new_with_name(key as u32))
+                .unwrap();
+        }
+        let key_of = |entry: Result<Option<(i64, TestValue)>>| entry.unwrap().map(|(k, _)| k);
+
+        assert_eq!(key_of(tree.get_lt(&0)), Some(-1));
+        assert_eq!(key_of(tree.get_lt(&-1)), Some(-300));
+        assert_eq!(key_of(tree.get_lt(&-300)), None);
+        assert_eq!(key_of(tree.get_gt(&-1)), Some(0));
+        assert_eq!(key_of(tree.get_gt(&1)), Some(256));
+        assert_eq!(key_of(tree.get_gt(&256)), None);
+
+        assert_eq!(key_of(tree.floor(&0)), Some(0));
+        assert_eq!(key_of(tree.floor(&255)), Some(0));
+        assert_eq!(key_of(tree.floor(&-2)), Some(-300));
+        assert_eq!(key_of(tree.floor(&i64::MIN)), None);
+        assert_eq!(key_of(tree.ceiling(&-1)), Some(-1));
+        assert_eq!(key_of(tree.ceiling(&-299)), Some(-1));
+        assert_eq!(key_of(tree.ceiling(&1)), Some(256));
+        assert_eq!(key_of(tree.ceiling(&257)), None);
+
+        let (key, value) = tree.floor(&100).unwrap().unwrap();
+        assert_eq!(key, 0);
+        assert_test_values_eq(&TestValue::new_with_name(0), &value);
+    }
+
+    #[test]
+    fn test_pop_min_and_max() {
+        let tree = create_temp_tree::<SignedKeySchema>().unwrap();
+        for key in [-2, 0, 3, 7] {
+            tree.insert(&key, &TestValue::new_with_name(key as u32))
+                .unwrap();
+        }
+
+        let (key, value) = tree.pop_min().unwrap().unwrap();
+        assert_eq!(key, -2);
+        assert_test_values_eq(&TestValue::new_with_name(-2_i64 as u32), &value);
+        assert_eq!(tree.pop_max().unwrap().unwrap().0, 7);
+
+        assert_eq!(tree.pop_max_range(..3).unwrap().unwrap().0, 0);
+        assert!(tree.pop_max_range(..3).unwrap().is_none());
+        assert!(tree.pop_min_range(4..).unwrap().is_none());
+        assert_eq!(tree.pop_min_range(-1..=3).unwrap().unwrap().0, 3);
+
+        assert!(tree.is_empty());
+        assert!(tree.pop_min().unwrap().is_none());
+        assert!(tree.pop_max().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_concurrent_pops_deliver_each_entry_once() {
+        let tree = create_test_tree().unwrap();
+        for key in 0..200 {
+            tree.insert(&key, &TestValue::new_with_name(key)).unwrap();
+        }
+
+        let mut popped: Vec<u32> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4)
+                .map(|worker| {
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        let mut keys = Vec::new();
+                        loop {
+                            let entry = match worker % 2 {
+                                0 => tree.pop_min(),
+                                _ => tree.pop_min_range(..150),
+                            };
+                            match entry.unwrap() {
+                                Some((key, _)) => keys.push(key),
+                                None => return keys,
+                            }
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        popped.sort_unstable();
+        assert_eq!(popped, (0..200).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_migrate_legacy_signed_keys() {
+        let db = create_test_db().unwrap();
+        let tree = db.get_tree::<SignedKeySchema>().unwrap();
+
+        // Write keys with the legacy layout, including values whose legacy
+        // encoding collides with another key's new encoding.
+        let keys = [-2_i64, -1, 0, 1, i64::MIN, i64::MAX];
+        for key in keys {
+            let value = <TestValue as ValueCodec<SignedKeySchema>>::encode_value(&TestValue::new(
+                key as u32,
+                &key.to_string(),
+            ))
+            .unwrap();
+            tree.inner.insert(key.to_be_bytes(), value).unwrap();
+        }
+
+        let migrated = db.migrate_legacy_signed_keys::<SignedKeySchema>().unwrap();
+        assert_eq!(migrated, keys.len());
+        // The migration is recorded, so running it again does not rewrite
+        // the keys a second time.
+        assert_eq!(
+            db.migrate_legacy_signed_keys::<SignedKeySchema>().unwrap(),
+            0
+        );
+
+        let items: Vec<_> = tree.iter().map(|item| item.unwrap()).collect();
+        let migrated_keys: Vec<_> = items.iter().map(|(k, _)| *k).collect();
+        assert_eq!(migrated_keys, vec![i64::MIN, -2, -1, 0, 1, i64::MAX]);
+        for (key, value) in items {
+            assert_test_values_eq(&value, &TestValue::new(key as u32, &key.to_string()));
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    struct CompositeKeySchema;
+
+    impl Schema for CompositeKeySchema {
+        const TREE_NAME: TreeName = TreeName("composite_keys");
+        type Key = (String, u64);
+        type Value = TestValue;
+    }
+
+    #[test]
+    fn test_composite_key_range() {
+        let tree = create_temp_tree::<CompositeKeySchema>().unwrap();
+
+        let keys = [
+            ("b", 1),
+            ("a", 3),
+            ("ab", 0),
+            ("a", 1),
+            ("", 7),
+            ("a\0", 2),
+            ("a", u64::MAX),
+        ];
+        for (name, height) in keys {
+            tree.insert(&(name.to_string(), height), &TestValue::alice())
+                .unwrap();
+        }
+
+        let all: Vec<_> = tree.iter().map(|item| item.unwrap().0).collect();
+        let mut expected: Vec<_> = keys.iter().map(|(n, h)| (n.to_string(), *h)).collect();
+        expected.sort();
+        assert_eq!(all, expected);
+
+        let account = "a".to_string();
+        let rows: Vec<_> = tree
+            .range((account.clone(), 0)..=(account.clone(), u64::MAX))
+            .unwrap()
+            .map(|item| item.unwrap().0.1)
+            .collect();
+        assert_eq!(rows, vec![1, 3, u64::MAX]);
+    }
+
+    #[derive(Debug, Clone)]
+    struct HeightKeySchema;
+
+    impl Schema for HeightKeySchema {
+        const TREE_NAME: TreeName = TreeName("height_keys");
+        type Key = (u32, u64);
+        type Value = TestValue;
+    }
+
+    #[derive(Debug, Clone)]
+    struct StringKeySchema;
+
+    impl Schema for StringKeySchema {
+        const TREE_NAME: TreeName = TreeName("string_keys");
+        type Key = String;
+        type Value = TestValue;
+    }
+
+    #[test]
+    fn test_scan_prefix_composite_key() {
+        let tree = create_temp_tree::<HeightKeySchema>().unwrap();
+
+        for (account, height) in [(2, 5), (1, 9), (1, 1), (3, 0), (1, 4), (256, 1)] {
+            tree.insert(&(account, height), &TestValue::new_with_name(account))
+                .unwrap();
+        }
+
+        let keys: Vec<_> = tree
+            .scan_prefix(&Prefix((1,)))
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![(1, 1), (1, 4), (1, 9)]);
+
+        let keys: Vec<_> = tree
+            .scan_prefix(&Prefix((1,)))
+            .unwrap()
+            .rev()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![(1, 9), (1, 4), (1, 1)]);
+
+        let keys: Vec<_> = tree
+            .scan_prefix(&Prefix((1, 4)))
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![(1, 4)]);
+
+        assert!(tree.scan_prefix(&Prefix((7,))).unwrap().next().is_none());
+    }
+
+    #[test]
+    fn test_scan_prefix_composite_string_key() {
+        let tree = create_temp_tree::<CompositeKeySchema>().unwrap();
+
+        for (name, height) in [("a", 2), ("ab", 1), ("a", 1), ("b", 0)] {
+            tree.insert(&(name.to_string(), height), &TestValue::alice())
+                .unwrap();
+        }
+
+        // Only exact matches of the first component are returned, not "ab".
+        let keys: Vec<_> = tree
+            .scan_prefix(&Prefix(("a".to_string(),)))
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec![("a".to_string(), 1), ("a".to_string(), 2)]);
+    }
+
+    #[test]
+    fn test_scan_prefix_string_key() {
+        let tree = create_temp_tree::<StringKeySchema>().unwrap();
+
+        for name in ["apple", "banana", "apricot", "ap", "a"] {
+            tree.insert(&name.to_string(), &TestValue::alice()).unwrap();
+        }
+
+        let keys: Vec<_> = tree
+            .scan_prefix("ap")
+            .unwrap()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec!["ap", "apple", "apricot"]);
+
+        let keys: Vec<_> = tree
+            .scan_prefix(&"apr".to_string())
+            .unwrap()
+            .rev()
+            .map(|item| item.unwrap().0)
+            .collect();
+        assert_eq!(keys, vec!["apricot"]);
+    }
+
+    #[test]
+    fn test_string_key_borrowed_lookups() {
+        let db = create_test_db().unwrap();
+        let tree = db.get_tree::<StringKeySchema>().unwrap();
+        tree.insert(&"apple".to_string(), &TestValue::alice())
+            .unwrap();
+
+        assert_test_values_eq(&TestValue::alice(), &tree.get("apple").unwrap().unwrap());
+        assert!(tree.get("app").unwrap().is_none());
+        assert!(tree.contains_key("apple").unwrap());
+        assert!(!tree.contains_key("banana").unwrap());
+
+        (&tree,)
+            .transaction(|(tx,)| {
+                assert!(tx.contains_key("apple")?);
+                assert!(tx.get("apple")?.is_some());
+                Ok::<_, ConflictableTransactionError<Error>>(())
+            })
+            .unwrap();
+    }
+
     #[test]
     fn test_rkyv_view_roundtrip() {
         let tree = create_archived_test_tree().unwrap();
@@ -717,6 +2039,65 @@ mod tests {
         assert_eq!(first.1.flags, expected.flags);
     }
 
+    #[derive(Debug, Clone)]
+    struct RkyvOwnedSchema;
+
+    impl Schema for RkyvOwnedSchema {
+        const TREE_NAME: TreeName = TreeName("rkyv_owned");
+        type Key = u32;
+        type Value = Rkyv<TestValue>;
+    }
+
+    #[derive(Debug, Clone)]
+    struct RkyvZeroCopySchema;
+
+    impl Schema for RkyvZeroCopySchema {
+        const TREE_NAME: TreeName = TreeName("rkyv_zero_copy");
+        type Key = u32;
+        type Value = Rkyv<TestValue, ZeroCopy>;
+    }
+
+    #[test]
+    fn test_rkyv_adapter_owned_roundtrip() {
+        let tree = create_temp_tree::<RkyvOwnedSchema>().unwrap();
+
+        tree.insert(&1, &Rkyv::new(TestValue::alice())).unwrap();
+        tree.insert(&2, &TestValue::bob().into()).unwrap();
+
+        let value: TestValue = tree.get(&1).unwrap().unwrap();
+        assert_test_values_eq(&value, &TestValue::alice());
+
+        let items: Vec<_> = tree.iter().map(|item| item.unwrap().1).collect();
+        assert_eq!(items, vec![TestValue::alice(), TestValue::bob()]);
+    }
+
+    #[test]
+    fn test_rkyv_adapter_zero_copy_roundtrip() {
+        let tree = create_temp_tree::<RkyvZeroCopySchema>().unwrap();
+
+        tree.insert(&1, &Rkyv::new(TestValue::charlie())).unwrap();
+
+        let view = tree.get(&1).unwrap().unwrap();
+        assert_eq!(view.id, 3);
+        assert_eq!(view.name.as_str(), "Charlie");
+    }
+
+    #[test]
+    fn test_rkyv_adapter_errors_carry_schema_name() {
+        let tree = create_temp_tree::<RkyvZeroCopySchema>().unwrap();
+        let key = <u32 as KeyCodec<RkyvZeroCopySchema>>::encode_key(&1).unwrap();
+        tree.inner.insert(key, vec![0xff_u8; 3]).unwrap();
+
+        match tree.get(&1) {
+            Err(crate::error::Error::CodecError(CodecError::DeserializationFailed {
+                schema,
+                ..
+            })) => assert_eq!(schema, RkyvZeroCopySchema::TREE_NAME.0),
+            Err(other) => panic!("expected deserialization error, got {other:?}"),
+            Ok(_) => panic!("expected deserialization error"),
+        }
+    }
+
     #[test]
     fn test_rkyv_view_rejects_invalid_bytes() {
         let tree = create_archived_test_tree().unwrap();
@@ -738,4 +2119,16 @@ mod tests {
             other => panic!("expected deserialization error, got {other:?}"),
         }
     }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_flush_async() {
+        let db = create_test_db().unwrap();
+        let tree = db.get_tree::<TestSchema1>().unwrap();
+        tree.insert(&1, &TestValue::alice()).unwrap();
+
+        block_on(tree.flush_async()).unwrap();
+        block_on(db.flush_async()).unwrap();
+        assert!(tree.contains_key(&1).unwrap());
+    }
 }
@@ -0,0 +1,414 @@
+use std::ops::Deref;
+
+use sled::IVec;
+
+use crate::{CodecError, CodecResult, Schema, ValueCodec, codec::CodecContext};
+
+/// Length of the version tag written in front of every value.
+const VERSION_LEN: usize = 4;
+
+/// A value type with a versioned layout, stored through [`Versioned`].
+///
+/// Implement this on the current value type with its [`VERSION`], and
+/// override [`decode_legacy`] to read older versions. Each older version is
+/// usually its own `VersionedValue` type that is upgraded with
+/// [`upgrade_from`], which forms an upgrade chain:
+///
+/// ```ignore
+/// impl VersionedValue<UserSchema> for UserV3 {
+///     const VERSION: u32 = 3;
+///
+///     fn decode_legacy(version: u32, buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self> {
+///         // `UserV2` in turn upgrades version 1 values.
+///         upgrade_from::<UserSchema, UserV2, _>(version, buf, ctx, UserV3::from)
+///     }
+/// }
+/// ```
+///
+/// [`VERSION`]: VersionedValue::VERSION
+/// [`decode_legacy`]: VersionedValue::decode_legacy
+pub trait VersionedValue<S: Schema>: ValueCodec<S, Decoded = Self> {
+    /// Version tag written in front of values encoded by this type.
+    ///
+    /// Bump it whenever the encoding changes, and never reuse a tag.
+    const VERSION: u32;
+
+    /// Decodes a value written with an older `version` and upgrades it.
+    ///
+    /// The default knows no older versions.
+    fn decode_legacy(version: u32, buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self> {
+        let _ = (buf, ctx);
+        Err(CodecError::UnknownVersion {
+            schema: S::TREE_NAME.0,
+            version,
+        })
+    }
+}
+
+/// Decodes a value stored with `version` as `Old`, following `Old`'s own
+/// upgrade chain for older versions, and converts it with `upgrade`.
+pub fn upgrade_from<S, Old, T>(
+    version: u32,
+    buf: IVec,
+    ctx: &CodecContext<'_>,
+    upgrade: impl FnOnce(Old) -> T,
+) -> CodecResult<T>
+where
+    S: Schema,
+    Old: VersionedValue<S>,
+{
+    let old = if version == Old::VERSION {
+        Old::decode_value_with(buf, ctx)?
+    } else if version < Old::VERSION {
+        Old::decode_legacy(version, buf, ctx)?
+    } else {
+        return Err(CodecError::UnknownVersion {
+            schema: S::TREE_NAME.0,
+            version,
+        });
+    };
+    Ok(upgrade(old))
+}
+
+/// Value codec envelope that prefixes the encoding of `T` with its version.
+///
+/// Values are stored as a 4-byte big-endian [`VersionedValue::VERSION`]
+/// followed by the encoding of `T`. Values written with an older version
+/// are decoded through [`VersionedValue::decode_legacy`], so they read
+/// transparently as the current type. They stay in the old layout until
+/// rewritten by [`SledTree::get_and_upgrade`](crate::SledTree::get_and_upgrade),
+/// [`SledDb::upgrade_all`](crate::SledDb::upgrade_all) or a regular write.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Versioned<T>(pub T);
+
+impl<T> Versioned<T> {
+    /// Returns the wrapped value.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Versioned<T> {
+    fn from(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T> Deref for Versioned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Reads the version tag in front of a stored value.
+fn stored_version(buf: &[u8]) -> Option<u32> {
+    let tag = buf.get(..VERSION_LEN)?;
+    Some(u32::from_be_bytes(tag.try_into().ok()?))
+}
+
+impl<S, T> ValueCodec<S> for Versioned<T>
+where
+    S: Schema,
+    T: VersionedValue<S>,
+{
+    type Decoded = T;
+
+    fn encode_value(&self) -> CodecResult<Vec<u8>> {
+        self.encode_value_with(&CodecContext::default())
+    }
+
+    fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+        Self::decode_value_with(buf, &CodecContext::default())
+    }
+
+    fn encode_value_with(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        Ok(with_version::<S, T>(&self.0.encode_value_with(ctx)?))
+    }
+
+    fn decode_value_with(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self::Decoded> {
+        let Some(version) = stored_version(&buf) else {
+            return Err(CodecError::Other(format!(
+                "missing version tag in '{}'",
+                S::TREE_NAME.0
+            )));
+        };
+        let body = buf.subslice(VERSION_LEN, buf.len() - VERSION_LEN);
+        upgrade_from::<S, T, T>(version, body, ctx, |value| value)
+    }
+
+    const STABLE_ENCODING: bool = T::STABLE_ENCODING;
+
+    /// Values stored by an older version keep their stored bytes, as they
+    /// never equal a value of the current version.
+    fn stable_form(buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<IVec> {
+        if stored_version(&buf) != Some(T::VERSION) {
+            return Ok(buf);
+        }
+        let body = T::stable_form(buf.subslice(VERSION_LEN, buf.len() - VERSION_LEN), ctx)?;
+        Ok(with_version::<S, T>(&body).into())
+    }
+
+    fn encode_stable_form(&self, ctx: &CodecContext<'_>) -> CodecResult<Vec<u8>> {
+        Ok(with_version::<S, T>(&self.0.encode_stable_form(ctx)?))
+    }
+}
+
+/// Prefixes `body` with the version tag of `T`.
+fn with_version<S: Schema, T: VersionedValue<S>>(body: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(VERSION_LEN + body.len());
+    buf.extend_from_slice(&T::VERSION.to_be_bytes());
+    buf.extend_from_slice(body);
+    buf
+}
+
+/// A value codec whose stored values can be rewritten in the current layout.
+pub trait UpgradableValue<S: Schema>: ValueCodec<S> {
+    /// Returns `true` if `buf` is already stored in the current layout.
+    fn is_current(buf: &[u8]) -> bool;
+
+    /// Converts a decoded value back into a value that can be written.
+    fn from_decoded(value: Self::Decoded) -> Self;
+}
+
+impl<S, T> UpgradableValue<S> for Versioned<T>
+where
+    S: Schema,
+    T: VersionedValue<S>,
+{
+    fn is_current(buf: &[u8]) -> bool {
+        stored_version(buf) == Some(T::VERSION)
+    }
+
+    fn from_decoded(value: T) -> Self {
+        Self(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rkyv::{Archive, Deserialize, Serialize};
+
+    use super::*;
+    use crate::{
+        KeyCodec, SledTree, TreeName,
+        codec::{decode_rkyv, encode_rkyv},
+        error::Error,
+        test_utils::*,
+    };
+
+    #[derive(Debug)]
+    struct UserSchema;
+
+    impl Schema for UserSchema {
+        const TREE_NAME: TreeName = TreeName("users");
+        type Key = u32;
+        type Value = Versioned<UserV3>;
+    }
+
+    /// Implements an owned `rkyv` value codec for a test type.
+    macro_rules! rkyv_value_codec {
+        ($($ty:ident),+) => {$(
+            impl ValueCodec<UserSchema> for $ty {
+                type Decoded = Self;
+
+                fn encode_value(&self) -> CodecResult<Vec<u8>> {
+                    encode_rkyv::<UserSchema, Self>(self)
+                }
+
+                fn decode_value(buf: IVec) -> CodecResult<Self::Decoded> {
+                    decode_rkyv::<UserSchema, Self>(buf)
+                }
+            }
+        )+};
+    }
+
+    #[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct UserV1 {
+        name: String,
+    }
+
+    #[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct UserV2 {
+        name: String,
+        age: u8,
+    }
+
+    #[derive(Archive, Serialize, Deserialize, Debug, Clone, PartialEq)]
+    struct UserV3 {
+        name: String,
+        age: u32,
+        verified: bool,
+    }
+
+    rkyv_value_codec!(UserV1, UserV2, UserV3);
+
+    impl VersionedValue<UserSchema> for UserV1 {
+        const VERSION: u32 = 1;
+    }
+
+    impl VersionedValue<UserSchema> for UserV2 {
+        const VERSION: u32 = 2;
+
+        fn decode_legacy(version: u32, buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self> {
+            upgrade_from::<UserSchema, UserV1, _>(version, buf, ctx, |v1| UserV2 {
+                name: v1.name,
+                age: 0,
+            })
+        }
+    }
+
+    impl VersionedValue<UserSchema> for UserV3 {
+        const VERSION: u32 = 3;
+
+        fn decode_legacy(version: u32, buf: IVec, ctx: &CodecContext<'_>) -> CodecResult<Self> {
+            upgrade_from::<UserSchema, UserV2, _>(version, buf, ctx, |v2| UserV3 {
+                name: v2.name,
+                age: v2.age.into(),
+                verified: false,
+            })
+        }
+    }
+
+    fn user(name: &str, age: u32) -> UserV3 {
+        UserV3 {
+            name: name.to_string(),
+            age,
+            verified: false,
+        }
+    }
+
+    /// Writes `value` as an older release would have.
+    fn insert_legacy<T: VersionedValue<UserSchema>>(
+        tree: &SledTree<UserSchema>,
+        key: u32,
+        value: T,
+    ) {
+        let key = <u32 as KeyCodec<UserSchema>>::encode_key(&key).unwrap();
+        let mut buf = T::VERSION.to_be_bytes().to_vec();
+        buf.extend(value.encode_value().unwrap());
+        tree.inner.insert(key, buf).unwrap();
+    }
+
+    fn raw_version(tree: &SledTree<UserSchema>, key: u32) -> Option<u32> {
+        let key = <u32 as KeyCodec<UserSchema>>::encode_key(&key).unwrap();
+        stored_version(&tree.inner.get(key).unwrap().unwrap())
+    }
+
+    fn insert_old_rows(tree: &SledTree<UserSchema>) {
+        insert_legacy(
+            tree,
+            1,
+            UserV1 {
+                name: "alice".into(),
+            },
+        );
+        insert_legacy(
+            tree,
+            2,
+            UserV2 {
+                name: "bob".into(),
+                age: 42,
+            },
+        );
+        tree.insert(&3, &Versioned(user("carol", 7))).unwrap();
+    }
+
+    #[test]
+    fn test_old_versions_read_transparently() {
+        let tree = create_temp_tree::<UserSchema>().unwrap();
+        insert_old_rows(&tree);
+
+        assert_eq!(tree.get(&1).unwrap(), Some(user("alice", 0)));
+        assert_eq!(tree.get(&2).unwrap(), Some(user("bob", 42)));
+        assert_eq!(tree.get(&3).unwrap(), Some(user("carol", 7)));
+
+        // Plain reads leave the stored layout alone.
+        assert_eq!(raw_version(&tree, 1), Some(1));
+        assert_eq!(raw_version(&tree, 3), Some(3));
+    }
+
+    #[test]
+    fn test_get_and_upgrade_rewrites_stale_values() {
+        let tree = create_temp_tree::<UserSchema>().unwrap();
+        insert_old_rows(&tree);
+
+        assert_eq!(tree.get_and_upgrade(&1).unwrap(), Some(user("alice", 0)));
+        assert_eq!(raw_version(&tree, 1), Some(3));
+        assert_eq!(raw_version(&tree, 2), Some(2));
+        assert_eq!(tree.get(&1).unwrap(), Some(user("alice", 0)));
+
+        assert_eq!(tree.get_and_upgrade(&3).unwrap(), Some(user("carol", 7)));
+        assert_eq!(tree.get_and_upgrade(&4).unwrap(), None);
+    }
+
+    #[test]
+    fn test_upgrade_losing_to_concurrent_write() {
+        let tree = create_temp_tree::<UserSchema>().unwrap();
+        insert_old_rows(&tree);
+        let key = <u32 as KeyCodec<UserSchema>>::encode_key(&1).unwrap();
+        let stale = tree.inner.get(&key).unwrap().unwrap();
+
+        // A write lands between reading the stale value and swapping it.
+        tree.insert(&1, &Versioned(user("alice", 30))).unwrap();
+        assert_eq!(
+            tree.upgrade_read(&key, stale.clone()).unwrap(),
+            Some(user("alice", 30))
+        );
+        assert_eq!(tree.get(&1).unwrap(), Some(user("alice", 30)));
+
+        tree.remove(&1).unwrap();
+        assert!(tree.upgrade_raw(&key, stale.clone()).unwrap().is_err());
+        assert_eq!(tree.upgrade_read(&key, stale).unwrap(), None);
+        assert_eq!(tree.get(&1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_upgrade_all() {
+        let db = create_test_db().unwrap();
+        let tree = db.get_tree::<UserSchema>().unwrap();
+        insert_old_rows(&tree);
+
+        assert_eq!(db.upgrade_all::<UserSchema>().unwrap(), 2);
+        for key in 1..=3 {
+            assert_eq!(raw_version(&tree, key), Some(UserV3::VERSION));
+        }
+        assert_eq!(tree.get(&2).unwrap(), Some(user("bob", 42)));
+        assert_eq!(db.upgrade_all::<UserSchema>().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_unknown_and_missing_versions_are_rejected() {
+        let tree = create_temp_tree::<UserSchema>().unwrap();
+        let key = <u32 as KeyCodec<UserSchema>>::encode_key(&1).unwrap();
+
+        tree.inner
+            .insert(&key, 4u32.to_be_bytes().to_vec())
+            .unwrap();
+        assert!(matches!(
+            tree.get(&1),
+            Err(Error::CodecError(CodecError::UnknownVersion {
+                version: 4,
+                ..
+            }))
+        ));
+
+        tree.inner
+            .insert(&key, 0u32.to_be_bytes().to_vec())
+            .unwrap();
+        assert!(matches!(
+            tree.get(&1),
+            Err(Error::CodecError(CodecError::UnknownVersion {
+                version: 0,
+                ..
+            }))
+        ));
+
+        tree.inner.insert(&key, vec![0_u8, 3]).unwrap();
+        assert!(matches!(
+            tree.get(&1),
+            Err(Error::CodecError(CodecError::Other(_)))
+        ));
+    }
+}
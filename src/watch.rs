@@ -0,0 +1,323 @@
+use std::{
+    fmt,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::{Arc, mpsc::RecvTimeoutError},
+    time::{Duration, Instant},
+};
+#[cfg(feature = "async")]
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[cfg(feature = "async")]
+use futures_core::Stream;
+use sled::IVec;
+
+use crate::{
+    KeyCodec, Schema, ValueCodec, codec::CodecContext, encryption::KeyProvider, error::Result,
+    tree::DecodedValue,
+};
+
+/// A change to a watched tree.
+pub enum Event<S: Schema> {
+    /// A key was set to a value.
+    Insert {
+        /// The key that was set.
+        key: S::Key,
+        /// The value it was set to.
+        value: DecodedValue<S>,
+    },
+    /// A key was removed.
+    Remove {
+        /// The key that was removed.
+        key: S::Key,
+    },
+}
+
+impl<S: Schema> Event<S> {
+    /// Returns the key the event is about.
+    pub fn key(&self) -> &S::Key {
+        match self {
+            Event::Insert { key, .. } | Event::Remove { key } => key,
+        }
+    }
+}
+
+impl<S: Schema> fmt::Debug for Event<S>
+where
+    S::Key: fmt::Debug,
+    DecodedValue<S>: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Insert { key, value } => f
+                .debug_struct("Insert")
+                .field("key", key)
+                .field("value", value)
+                .finish(),
+            Event::Remove { key } => f.debug_struct("Remove").field("key", key).finish(),
+        }
+    }
+}
+
+/// A raw key range of a [`Subscriber`].
+type RawRange = (Bound<IVec>, Bound<IVec>);
+
+/// Returns the longest prefix shared by every key in `range`.
+fn common_prefix(range: &RawRange) -> Vec<u8> {
+    let (Bound::Included(start) | Bound::Excluded(start)) = &range.0 else {
+        return Vec::new();
+    };
+    let (Bound::Included(end) | Bound::Excluded(end)) = &range.1 else {
+        return Vec::new();
+    };
+    let len = start
+        .iter()
+        .zip(end.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    start[..len].to_vec()
+}
+
+/// A typed subscription to the changes of a tree, created with
+/// [`SledTree::watch`](crate::SledTree::watch) or
+/// [`SledTree::watch_range`](crate::SledTree::watch_range).
+///
+/// Iterating blocks until the next change and ends when the database shuts
+/// down. Changes that fail to decode are yielded as errors. With the `async`
+/// feature the subscriber is also a `Stream` yielding the same items.
+pub struct Subscriber<S: Schema> {
+    pub(crate) inner: sled::Subscriber,
+    range: RawRange,
+    key_provider: Option<Arc<dyn KeyProvider>>,
+    _phantom: PhantomData<S>,
+}
+
+impl<S: Schema> fmt::Debug for Subscriber<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("tree_name", &S::TREE_NAME.0)
+            .field("schema", &std::any::type_name::<S>())
+            .finish()
+    }
+}
+
+impl<S: Schema> Subscriber<S> {
+    /// Subscribes to the changes of `tree` within `range`.
+    pub(crate) fn new(
+        tree: &sled::Tree,
+        range: RawRange,
+        key_provider: Option<Arc<dyn KeyProvider>>,
+    ) -> Self {
+        let inner = tree.watch_prefix(common_prefix(&range));
+        Self {
+            inner,
+            range,
+            key_provider,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Waits up to `timeout` for the next change.
+    ///
+    /// Fails with [`RecvTimeoutError::Timeout`] if no change arrives in time
+    /// and with [`RecvTimeoutError::Disconnected`] once the database shuts
+    /// down.
+    pub fn next_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> std::result::Result<Result<Event<S>>, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = self.inner.next_timeout(remaining)?;
+            if let Some(event) = self.decode(event) {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Decodes a raw event, or returns `None` if its key is out of range.
+    pub(crate) fn decode(&self, event: sled::Event) -> Option<Result<Event<S>>> {
+        if !self.range.contains(event.key()) {
+            return None;
+        }
+        let decoded = match event {
+            sled::Event::Insert { key, value } => {
+                let ctx = CodecContext::new(self.key_provider.as_deref()).with_key(&key);
+                S::Key::decode_key(&key).and_then(|decoded_key| {
+                    Ok(Event::Insert {
+                        key: decoded_key,
+                        value: S::Value::decode_value_with(value, &ctx)?,
+                    })
+                })
+            }
+            sled::Event::Remove { key } => {
+                S::Key::decode_key(&key).map(|key| Event::Remove { key })
+            }
+        };
+        Some(decoded.map_err(Into::into))
+    }
+}
+
+impl<S: Schema> Iterator for Subscriber<S> {
+    type Item = Result<Event<S>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let event = self.inner.next()?;
+            if let Some(event) = self.decode(event) {
+                return Some(event);
+            }
+        }
+    }
+}
+
+// The subscriber is never structurally pinned.
+#[cfg(feature = "async")]
+impl<S: Schema> Unpin for Subscriber<S> {}
+
+#[cfg(feature = "async")]
+impl<S: Schema> Stream for Subscriber<S> {
+    type Item = Result<Event<S>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            match Pin::new(&mut this.inner).poll(cx) {
+                Poll::Ready(Some(event)) => {
+                    if let Some(event) = this.decode(event) {
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::{CodecError, error::Error, test_utils::*};
+
+    const TIMEOUT: Duration = Duration::from_millis(50);
+
+    fn next(subscriber: &mut Subscriber<TestSchema1>) -> Event<TestSchema1> {
+        subscriber
+            .next_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_watch_reports_inserts_and_removes() {
+        let tree = create_temp_tree::<TestSchema1>().unwrap();
+        let mut subscriber = tree.watch();
+
+        tree.insert(&1, &TestValue::alice()).unwrap();
+        tree.remove(&1).unwrap();
+
+        match next(&mut subscriber) {
+            Event::Insert { key, value } => {
+                assert_eq!(key, 1);
+                assert_test_values_eq(&TestValue::alice(), &value);
+            }
+            other => panic!("expected insert, got {other:?}"),
+        }
+        assert!(matches!(next(&mut subscriber), Event::Remove { key: 1 }));
+        assert!(matches!(
+            subscriber.next_timeout(TIMEOUT),
+            Err(RecvTimeoutError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_watch_range_filters_keys() {
+        let tree = create_temp_tree::<TestSchema1>().unwrap();
+        let mut subscriber = tree.watch_range(2..4).unwrap();
+
+        for key in [1, 2, 5, 3, 4] {
+            tree.insert(&key, &TestValue::new_with_name(key)).unwrap();
+        }
+
+        assert_eq!(*next(&mut subscriber).key(), 2);
+        assert_eq!(*next(&mut subscriber).key(), 3);
+        assert!(matches!(
+            subscriber.next_timeout(TIMEOUT),
+            Err(RecvTimeoutError::Timeout)
+        ));
+    }
+
+    #[test]
+    fn test_watch_blocks_until_change() {
+        let tree = create_temp_tree::<TestSchema1>().unwrap();
+        let subscriber = tree.watch();
+
+        let writer = {
+            let tree = tree.clone();
+            thread::spawn(move || {
+                thread::sleep(TIMEOUT);
+                tree.insert(&7, &TestValue::new_with_name(7)).unwrap();
+                tree.remove(&7).unwrap();
+            })
+        };
+
+        let keys: Vec<_> = subscriber
+            .take(2)
+            .map(|event| *event.unwrap().key())
+            .collect();
+        assert_eq!(keys, vec![7, 7]);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_watch_reports_decode_failures() {
+        let tree = create_temp_tree::<TestSchema1>().unwrap();
+        let mut subscriber = tree.watch();
+
+        let key = <u32 as KeyCodec<TestSchema1>>::encode_key(&1).unwrap();
+        tree.inner.insert(key, vec![0xff_u8; 3]).unwrap();
+
+        assert!(matches!(
+            subscriber.next_timeout(Duration::from_secs(5)),
+            Ok(Err(Error::CodecError(
+                CodecError::DeserializationFailed { .. }
+            )))
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_subscriber_is_a_stream() {
+        let tree = create_temp_tree::<TestSchema1>().unwrap();
+        let mut subscriber = tree.watch_range(5..).unwrap();
+
+        let writer = {
+            let tree = tree.clone();
+            thread::spawn(move || {
+                thread::sleep(TIMEOUT);
+                tree.insert(&1, &TestValue::new_with_name(1)).unwrap();
+                tree.insert(&6, &TestValue::new_with_name(6)).unwrap();
+            })
+        };
+
+        let event = block_on(std::future::poll_fn(|cx| {
+            Pin::new(&mut subscriber).poll_next(cx)
+        }));
+        match event.unwrap().unwrap() {
+            Event::Insert { key, value } => {
+                assert_eq!(key, 6);
+                assert_test_values_eq(&TestValue::new_with_name(6), &value);
+            }
+            other => panic!("expected insert, got {other:?}"),
+        }
+        writer.join().unwrap();
+    }
+}
@@ -0,0 +1,128 @@
+//! Tests for the derive macros exposed behind the `derive` feature.
+
+use base64 as _;
+#[cfg(feature = "bincode")]
+use bincode as _;
+#[cfg(feature = "encryption")]
+use chacha20poly1305 as _;
+#[cfg(feature = "checksum")]
+use crc32c as _;
+use dashmap as _;
+#[cfg(feature = "async")]
+use futures_core as _;
+#[cfg(feature = "lz4")]
+use lz4_flex as _;
+#[cfg(feature = "postcard")]
+use postcard as _;
+use rkyv::{Archive, Deserialize, Serialize};
+#[cfg(any(feature = "serde-json", feature = "bincode", feature = "postcard"))]
+use serde as _;
+#[cfg(feature = "serde-json")]
+use serde_json as _;
+use thiserror as _;
+use typed_sled::{KeyCodec, RkyvValueCodec, Schema, SledDb, TreeName};
+use typed_sled_derive as _;
+
+#[derive(Archive, Serialize, Deserialize, RkyvValueCodec, Debug, Clone, PartialEq)]
+struct User {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, Schema)]
+#[schema(tree = "users", key = u32, value = User)]
+struct UserSchema;
+
+#[derive(KeyCodec, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct BalanceKey {
+    account: String,
+    height: u64,
+}
+
+#[derive(KeyCodec, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct SeqKey(u32, i64);
+
+#[derive(Debug, Schema)]
+#[schema(tree = "balances", key = BalanceKey, value = User)]
+struct BalanceSchema;
+
+#[derive(Debug, Schema)]
+#[schema(tree = "seqs", key = SeqKey, value = User, version = 2)]
+struct SeqSchema;
+
+fn create_db() -> SledDb {
+    let sled_db = sled::Config::new().temporary(true).open().unwrap();
+    SledDb::new(sled_db).unwrap()
+}
+
+fn user(id: u32) -> User {
+    User {
+        id,
+        name: format!("user {id}"),
+    }
+}
+
+#[test]
+fn test_derive_schema() {
+    assert_eq!(UserSchema::TREE_NAME, TreeName("users"));
+    assert_eq!(BalanceSchema::TREE_NAME, TreeName("balances"));
+    assert_eq!(UserSchema::VERSION, 0);
+    assert_eq!(SeqSchema::VERSION, 2);
+}
+
+#[test]
+fn test_derive_rkyv_value_codec_roundtrip() {
+    let db = create_db();
+    let users = db.get_tree::<UserSchema>().unwrap();
+
+    users.insert(&1, &user(1)).unwrap();
+    assert_eq!(users.get(&1).unwrap(), Some(user(1)));
+    assert_eq!(users.get(&2).unwrap(), None);
+}
+
+#[test]
+fn test_derive_key_codec_named_fields() {
+    let db = create_db();
+    let balances = db.get_tree::<BalanceSchema>().unwrap();
+
+    let keys = [("b", 1), ("a", 2), ("ab", 0), ("a", 1)].map(|(account, height)| BalanceKey {
+        account: account.to_string(),
+        height,
+    });
+    for (i, key) in keys.iter().enumerate() {
+        balances.insert(key, &user(i as u32)).unwrap();
+    }
+
+    let stored: Vec<_> = balances.iter().map(|item| item.unwrap().0).collect();
+    let mut expected = keys.to_vec();
+    expected.sort();
+    assert_eq!(stored, expected);
+
+    let range: Vec<_> = balances
+        .range(
+            BalanceKey {
+                account: "a".to_string(),
+                height: 0,
+            }..=BalanceKey {
+                account: "a".to_string(),
+                height: u64::MAX,
+            },
+        )
+        .unwrap()
+        .map(|item| item.unwrap().0.height)
+        .collect();
+    assert_eq!(range, vec![1, 2]);
+}
+
+#[test]
+fn test_derive_key_codec_tuple_struct() {
+    let key = SeqKey(3, -7);
+    let encoded = <SeqKey as KeyCodec<SeqSchema>>::encode_key(&key).unwrap();
+    let tuple_encoded = <(u32, i64) as KeyCodec<SeqSchema>>::encode_key(&(3, -7)).unwrap();
+    assert_eq!(encoded, tuple_encoded);
+
+    let decoded = <SeqKey as KeyCodec<SeqSchema>>::decode_key(&encoded).unwrap();
+    assert_eq!(decoded, key);
+
+    assert!(<SeqKey as KeyCodec<SeqSchema>>::decode_key(&encoded[1..]).is_err());
+}
@@ -0,0 +1,231 @@
+//! # typed-sled-derive
+//!
+//! Derive macros for [`typed-sled`](https://github.com/alpenlabs/typed-sled).
+//!
+//! These are re-exported by `typed-sled` behind its `derive` feature and
+//! should be used through that crate rather than depended on directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{
+    Data, DeriveInput, Fields, Ident, LitInt, LitStr, Type, parse_macro_input, parse_quote,
+    spanned::Spanned,
+};
+
+/// Derives `typed_sled::Schema` from a `#[schema(...)]` attribute.
+///
+/// ```ignore
+/// #[derive(Debug, Schema)]
+/// #[schema(tree = "users", key = u32, value = User)]
+/// struct UserSchema;
+/// ```
+///
+/// An optional `version = N` sets `Schema::VERSION`.
+#[proc_macro_derive(Schema, attributes(schema))]
+pub fn derive_schema(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_schema(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `typed_sled::ValueCodec<S>` for every schema `S` using `rkyv`.
+///
+/// The type must also derive `rkyv::Archive`, `rkyv::Serialize` and
+/// `rkyv::Deserialize`. Values decode into an owned `Self`.
+#[proc_macro_derive(RkyvValueCodec)]
+pub fn derive_rkyv_value_codec(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_rkyv_value_codec(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Derives `typed_sled::KeyCodec<S>` for a struct whose fields are all key
+/// codec types.
+///
+/// Fields are encoded in declaration order like a tuple key, so keys sort by
+/// their first field, then their second, and so on.
+#[proc_macro_derive(KeyCodec)]
+pub fn derive_key_codec(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_key_codec(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_schema(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut tree: Option<LitStr> = None;
+    let mut key: Option<Type> = None;
+    let mut value: Option<Type> = None;
+    let mut version: Option<LitInt> = None;
+
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("schema")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tree") {
+                tree = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("key") {
+                key = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("value") {
+                value = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("version") {
+                version = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `tree`, `key`, `value` or `version`"));
+            }
+            Ok(())
+        })?;
+    }
+
+    let missing = |name: &str| {
+        syn::Error::new(
+            input.ident.span(),
+            format!("missing `{name}` in `#[schema(tree = \"..\", key = .., value = ..)]`"),
+        )
+    };
+    let tree = tree.ok_or_else(|| missing("tree"))?;
+    let key = key.ok_or_else(|| missing("key"))?;
+    let value = value.ok_or_else(|| missing("value"))?;
+
+    let version = version.map(|version| quote!(const VERSION: u32 = #version;));
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::typed_sled::Schema for #name #ty_generics #where_clause {
+            const TREE_NAME: ::typed_sled::TreeName = ::typed_sled::TreeName(#tree);
+            type Key = #key;
+            type Value = #value;
+            #version
+        }
+    })
+}
+
+fn expand_rkyv_value_codec(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let schema = schema_param(&input);
+
+    let mut generics = input.generics.clone();
+    generics
+        .params
+        .push(parse_quote!(#schema: ::typed_sled::Schema));
+    let (impl_generics, _, _) = generics.split_for_impl();
+    let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::typed_sled::ValueCodec<#schema> for #name #ty_generics #where_clause {
+            type Decoded = Self;
+
+            fn encode_value(&self) -> ::typed_sled::CodecResult<::std::vec::Vec<u8>> {
+                ::typed_sled::codec::encode_rkyv::<#schema, Self>(self)
+            }
+
+            fn decode_value(
+                buf: ::typed_sled::__private::IVec,
+            ) -> ::typed_sled::CodecResult<Self::Decoded> {
+                ::typed_sled::codec::decode_rkyv::<#schema, Self>(buf)
+            }
+        }
+    })
+}
+
+fn expand_key_codec(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let schema = schema_param(&input);
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.span(),
+            "`KeyCodec` can only be derived for structs",
+        ));
+    };
+
+    let field_types: Vec<&Type> = data.fields.iter().map(|f| &f.ty).collect();
+    let members: Vec<TokenStream2> = data
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| match &field.ident {
+            Some(ident) => quote!(#ident),
+            None => {
+                let idx = syn::Index::from(idx);
+                quote!(#idx)
+            }
+        })
+        .collect();
+    let vars: Vec<Ident> = (0..field_types.len())
+        .map(|idx| format_ident!("__field{}", idx))
+        .collect();
+    let construct = match &data.fields {
+        Fields::Named(_) => quote!(Self { #(#members: #vars),* }),
+        Fields::Unnamed(_) => quote!(Self(#(#vars),*)),
+        Fields::Unit => quote!(Self),
+    };
+
+    let mut generics = input.generics.clone();
+    generics
+        .params
+        .push(parse_quote!(#schema: ::typed_sled::Schema));
+    {
+        let where_clause = generics.make_where_clause();
+        for ty in &field_types {
+            where_clause
+                .predicates
+                .push(parse_quote!(#ty: ::typed_sled::KeyCodec<#schema>));
+        }
+    }
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::typed_sled::KeyCodec<#schema> for #name #ty_generics #where_clause {
+            fn encode_key(&self) -> ::typed_sled::CodecResult<::std::vec::Vec<u8>> {
+                ::typed_sled::codec::encode_composite_key::<#schema, Self>(self)
+            }
+
+            fn decode_key(buf: &[u8]) -> ::typed_sled::CodecResult<Self> {
+                ::typed_sled::codec::decode_composite_key::<#schema, Self>(buf)
+            }
+
+            fn encode_key_component(
+                &self,
+                buf: &mut ::std::vec::Vec<u8>,
+            ) -> ::typed_sled::CodecResult<()> {
+                #(<#field_types as ::typed_sled::KeyCodec<#schema>>::encode_key_component(
+                    &self.#members,
+                    buf,
+                )?;)*
+                ::std::result::Result::Ok(())
+            }
+
+            fn decode_key_component(buf: &[u8]) -> ::typed_sled::CodecResult<(Self, usize)> {
+                #[allow(unused_mut)]
+                let mut pos = 0;
+                #(
+                    let (#vars, len) =
+                        <#field_types as ::typed_sled::KeyCodec<#schema>>::decode_key_component(
+                            &buf[pos..],
+                        )?;
+                    pos += len;
+                )*
+                ::std::result::Result::Ok((#construct, pos))
+            }
+        }
+    })
+}
+
+/// Picks a name for the schema type parameter that cannot clash with the
+/// input's own generics.
+fn schema_param(input: &DeriveInput) -> Ident {
+    let taken: Vec<String> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.to_string())
+        .collect();
+    let mut name = String::from("__S");
+    while taken.contains(&name) {
+        name.push('_');
+    }
+    Ident::new(&name, Span::call_site())
+}